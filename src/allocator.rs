@@ -2,74 +2,87 @@ use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AllocatorsRoot {
     pub zones: Vec<Zone>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Zone {
     pub zone_id: String,
     pub allocators: Vec<Allocator>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Status {
     pub connected: bool,
     pub healthy: bool,
+    #[serde(default)]
     pub maintenance_mode: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Capacity {
     pub memory: Memory,
+    // Only reported by some ECE versions
+    pub cpu: Option<Cpu>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Memory {
     pub total: u64,
     pub used: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Cpu {
+    pub total: f64,
+    pub used: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlansInfo {
     pub pending: bool,
     pub version: Option<String>,
     pub zone_count: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Instance {
+    // ECE 3.x reports the product type rather than the cluster type
+    #[serde(alias = "product_type")]
     pub cluster_type: String,
     pub cluster_id: String,
     pub cluster_name: Option<String>,
     pub instance_name: String,
+    #[serde(default)]
     pub node_memory: u64,
     pub healthy: Option<bool>,
     pub cluster_healthy: Option<bool>,
+    #[serde(default)]
     pub instance_configuration_id: String,
     pub moving: Option<bool>,
     pub plans_info: Option<PlansInfo>,
     pub deployment_id: Option<String>,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct KeyValue {
     pub key: String,
     pub value: String,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildInfo {
     pub commit_hash: String,
     pub version: String,
 }
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ExternalLink {
     pub id: String,
     pub label: String,
     pub uri: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Allocator {
     pub status: Status,
     pub allocator_id: String,
@@ -77,10 +90,21 @@ pub struct Allocator {
     pub host_ip: String,
     pub public_hostname: String,
     pub capacity: Capacity,
+    // Fields below are absent or moved on some ECE versions
+    #[serde(default)]
     pub settings: HashMap<String, Value>,
+    #[serde(default)]
     pub instances: Vec<Instance>,
+    #[serde(default)]
     pub metadata: Vec<KeyValue>,
-    pub build_info: BuildInfo,
+    pub build_info: Option<BuildInfo>,
+    #[serde(default)]
     pub features: Vec<String>,
+    #[serde(default)]
     pub external_links: Vec<ExternalLink>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlatformInfo {
+    pub version: String,
+}
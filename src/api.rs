@@ -0,0 +1,35 @@
+use core::time::Duration;
+use futures_util::future::BoxFuture;
+use http_body_util::BodyExt;
+use hyper::{Request, Response};
+
+use crate::error::Error as RestError;
+use crate::https::{HttpsClient, RequestBody, ResponseBody};
+
+// Transport the collectors reach ECE through, implemented by HttpsClient
+// and, in tests, by a mock serving recorded ECE fixtures
+pub trait EceApi: Send + Sync + std::fmt::Debug {
+    fn send(
+        &self,
+        req: Request<RequestBody>,
+    ) -> BoxFuture<'_, Result<Response<ResponseBody>, RestError>>;
+
+    // How long callers should wait for a response once connected
+    fn request_timeout(&self) -> Duration;
+}
+
+impl EceApi for HttpsClient {
+    fn send(
+        &self,
+        req: Request<RequestBody>,
+    ) -> BoxFuture<'_, Result<Response<ResponseBody>, RestError>> {
+        Box::pin(async move {
+            let response = self.request(req).await?;
+            Ok(response.map(|body| body.boxed()))
+        })
+    }
+
+    fn request_timeout(&self) -> Duration {
+        HttpsClient::request_timeout(self)
+    }
+}
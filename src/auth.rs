@@ -0,0 +1,42 @@
+use axum::{
+    extract::Request,
+    http::{header::AUTHORIZATION, StatusCode},
+    middleware::Next,
+    response::IntoResponse,
+};
+
+use crate::State;
+
+// Reject requests that do not carry the configured bearer token, if one is configured
+pub async fn require_token(req: Request, next: Next) -> impl IntoResponse {
+    let expected = req
+        .extensions()
+        .get::<State>()
+        .and_then(|state| state.debug_token.clone());
+    let supplied = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    match (expected, supplied) {
+        (None, _) => Ok(next.run(req).await),
+        (Some(expected), Some(supplied)) if constant_time_eq(&expected, &supplied) => {
+            Ok(next.run(req).await)
+        }
+        _ => {
+            log::warn!("{{\"fn\": \"require_token\", \"error\": \"unauthorized\"}}");
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+// Compare without short circuiting, so response timing does not leak the token
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Longest window reported, in minutes
+const RETENTION: u64 = 60;
+
+// Outcomes of ECE API calls in one minute buckets, for rolling success rates
+#[derive(Debug, Default)]
+pub struct Availability {
+    // (minute, successful calls, total calls), oldest first
+    buckets: VecDeque<(u64, u64, u64)>,
+}
+
+impl Availability {
+    pub fn record(&mut self, success: bool) {
+        let minute = now_minute();
+        match self.buckets.back_mut() {
+            Some((last, ok, total)) if *last == minute => {
+                *ok += success as u64;
+                *total += 1;
+            }
+            _ => self.buckets.push_back((minute, success as u64, 1)),
+        }
+        while let Some((first, _, _)) = self.buckets.front() {
+            if *first + RETENTION > minute {
+                break;
+            }
+            self.buckets.pop_front();
+        }
+    }
+
+    // Share of calls that succeeded over the last minutes, None without any calls
+    pub fn ratio(&self, minutes: u64) -> Option<f64> {
+        let since = now_minute().saturating_sub(minutes);
+        let (ok, total) = self
+            .buckets
+            .iter()
+            .filter(|(minute, _, _)| *minute > since)
+            .fold((0, 0), |(ok, total), (_, o, t)| (ok + o, total + t));
+        match total {
+            0 => None,
+            total => Some(ok as f64 / total as f64),
+        }
+    }
+}
+
+fn now_minute() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 60
+}
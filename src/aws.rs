@@ -0,0 +1,54 @@
+use serde_json::Value;
+use std::error::Error;
+
+type BoxResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Resolve a secret reference of the form aws-sm://name[#key] or aws-ssm://name,
+// authenticating through the default AWS credential chain (env, IRSA, instance profile)
+pub async fn fetch_secret(source: &str) -> BoxResult<String> {
+    let config = aws_config::load_from_env().await;
+
+    if let Some(name) = source.strip_prefix("aws-sm://") {
+        // An optional #key selects a field when the secret holds a JSON object
+        let (name, key) = match name.split_once('#') {
+            Some((name, key)) => (name, Some(key)),
+            None => (name, None),
+        };
+        let client = aws_sdk_secretsmanager::Client::new(&config);
+        let output = client.get_secret_value().secret_id(name).send().await?;
+        let secret = output
+            .secret_string()
+            .ok_or_else(|| format!("secret {} has no string value", name))?;
+        return match key {
+            Some(key) => {
+                let value: Value = serde_json::from_str(secret)?;
+                value[key]
+                    .as_str()
+                    .map(str::to_string)
+                    .ok_or_else(|| format!("key {} not found in secret {}", key, name).into())
+            }
+            None => Ok(secret.trim().to_string()),
+        };
+    }
+
+    if let Some(name) = source.strip_prefix("aws-ssm://") {
+        let client = aws_sdk_ssm::Client::new(&config);
+        let output = client
+            .get_parameter()
+            .name(name)
+            .with_decryption(true)
+            .send()
+            .await?;
+        return output
+            .parameter()
+            .and_then(|p| p.value())
+            .map(|v| v.trim().to_string())
+            .ok_or_else(|| format!("parameter {} has no value", name).into());
+    }
+
+    Err(format!(
+        "unsupported secret source {}, expected aws-sm:// or aws-ssm://",
+        source
+    )
+    .into())
+}
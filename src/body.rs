@@ -0,0 +1,165 @@
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+use crate::https::ResponseBody;
+
+// Buffer a whole response body, for the small bodies parsed in one piece
+pub async fn to_bytes<B: Body<Data = Bytes>>(body: B) -> Result<Bytes, B::Error> {
+    Ok(body.collect().await?.to_bytes())
+}
+
+// Number of chunks buffered between the socket and the parser
+const CHUNK_BUFFER: usize = 16;
+
+// Blocking reader over a streaming response body, so that serde can
+// deserialize directly from the socket instead of from a buffered copy.
+// Must only be read from outside of the async runtime, e.g. spawn_blocking.
+// Reading stops with an error once more than max_bytes have been received,
+// or when the body hasn't fully arrived by the deadline.
+pub struct BodyReader {
+    rx: mpsc::Receiver<io::Result<Bytes>>,
+    current: Bytes,
+    received: Arc<AtomicU64>,
+    timed_out: Arc<AtomicBool>,
+}
+
+impl BodyReader {
+    pub fn new(mut body: ResponseBody, max_bytes: u64, deadline: Instant) -> Self {
+        let (tx, rx) = mpsc::channel(CHUNK_BUFFER);
+        let received = Arc::new(AtomicU64::new(0));
+        let counter = received.clone();
+        let timed_out = Arc::new(AtomicBool::new(false));
+        let expired = timed_out.clone();
+        tokio::spawn(async move {
+            loop {
+                let frame = match tokio::time::timeout_at(deadline, body.frame()).await {
+                    Ok(Some(frame)) => frame,
+                    Ok(None) => break,
+                    Err(_) => {
+                        expired.store(true, Ordering::Relaxed);
+                        let error = io::Error::new(io::ErrorKind::TimedOut, "response timed out");
+                        let _ = tx.send(Err(error)).await;
+                        break;
+                    }
+                };
+                // Trailers carry no data, skip them
+                let chunk = match frame.map(|frame| frame.into_data()) {
+                    Ok(Ok(chunk)) => Ok(chunk),
+                    Ok(Err(_)) => continue,
+                    Err(e) => Err(e),
+                };
+                let chunk = chunk
+                    .map_err(|e| io::Error::other(e.to_string()))
+                    .and_then(|chunk| {
+                        let total = counter.fetch_add(chunk.len() as u64, Ordering::Relaxed)
+                            + chunk.len() as u64;
+                        match total > max_bytes {
+                            true => Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                format!("response exceeded {} bytes", max_bytes),
+                            )),
+                            false => Ok(chunk),
+                        }
+                    });
+                let failed = chunk.is_err();
+                if tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+        BodyReader {
+            rx,
+            current: Bytes::new(),
+            received,
+            timed_out,
+        }
+    }
+
+    // Handle on the number of body bytes received so far
+    pub fn received(&self) -> Arc<AtomicU64> {
+        self.received.clone()
+    }
+
+    // Handle on whether the deadline passed before the body was received
+    pub fn timed_out(&self) -> Arc<AtomicBool> {
+        self.timed_out.clone()
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.current = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+// Reader that errors once more than max_bytes have been read from the inner
+// reader, used to bound the size of a decompressed response
+pub struct LimitedReader<R> {
+    inner: R,
+    max_bytes: u64,
+    read: Arc<AtomicU64>,
+}
+
+impl<R: Read> LimitedReader<R> {
+    pub fn new(inner: R, max_bytes: u64, read: Arc<AtomicU64>) -> Self {
+        LimitedReader {
+            inner,
+            max_bytes,
+            read,
+        }
+    }
+}
+
+impl<R: Read> Read for LimitedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        let total = self.read.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        match total > self.max_bytes {
+            true => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("decompressed response exceeded {} bytes", self.max_bytes),
+            )),
+            false => Ok(len),
+        }
+    }
+}
+
+// Reader that keeps a copy of everything read through it
+pub struct TeeReader<R> {
+    inner: R,
+    copy: Vec<u8>,
+}
+
+impl<R: Read> TeeReader<R> {
+    pub fn new(inner: R) -> Self {
+        TeeReader {
+            inner,
+            copy: Vec::new(),
+        }
+    }
+
+    pub fn into_copy(self) -> Vec<u8> {
+        self.copy
+    }
+}
+
+impl<R: Read> Read for TeeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.copy.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+}
@@ -0,0 +1,211 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use crate::allocator;
+#[cfg(feature = "deployments")]
+use crate::deployment;
+use crate::error::Error as RestError;
+#[cfg(feature = "deployments")]
+use crate::state::DEPLOYMENTS_SEARCH_PATH;
+use crate::state::{State, ALLOCATORS_PATH};
+
+mod allocators;
+#[cfg(feature = "deployments")]
+mod cluster_health;
+#[cfg(feature = "deployments")]
+mod deployments;
+mod elasticsearch_clusters;
+#[cfg(feature = "deployments")]
+mod endpoints;
+#[cfg(feature = "deployments")]
+mod ilm;
+#[cfg(feature = "deployments")]
+mod indices;
+#[cfg(feature = "deployments")]
+mod jvm_heap;
+mod kibana_clusters;
+mod proxies;
+#[cfg(feature = "deployments")]
+mod remote_clusters;
+#[cfg(feature = "deployments")]
+mod resources;
+#[cfg(feature = "deployments")]
+mod shard_allocation;
+#[cfg(feature = "deployments")]
+mod slm;
+
+#[cfg(feature = "deployments")]
+use resources::Placements;
+
+// ECE reports memory in MB
+pub const BYTES_PER_MB: f64 = 1024.0 * 1024.0;
+
+// One ECE endpoint turned into series. New endpoints are added as a type
+// implementing this and registered in Registry::builtin.
+#[async_trait]
+pub trait Collector: Send + Sync {
+    type Payload: Send;
+
+    // Name accepted by --collectors
+    fn name(&self) -> &str;
+
+    // ECE API path the series come from, for ece_data_age_seconds
+    fn path(&self) -> &str;
+
+    // Whether it exports a series set per cluster, which --aggregate-only rules out
+    fn per_cluster(&self) -> bool {
+        false
+    }
+
+    // Fetch the ECE response, parsed as it streams in
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError>;
+
+    // Export the parsed response
+    async fn emit(&self, state: &State, payload: Self::Payload) -> Result<(), RestError>;
+}
+
+// Object safe form of a Collector, as kept by the registry
+#[async_trait]
+pub trait Collect: Send + Sync {
+    fn name(&self) -> &str;
+    fn path(&self) -> &str;
+    fn per_cluster(&self) -> bool;
+    async fn collect(&self, state: &State, collection: &Collection) -> Result<(), RestError>;
+}
+
+#[async_trait]
+impl<C: Collector> Collect for C {
+    fn name(&self) -> &str {
+        Collector::name(self)
+    }
+
+    fn path(&self) -> &str {
+        Collector::path(self)
+    }
+
+    fn per_cluster(&self) -> bool {
+        Collector::per_cluster(self)
+    }
+
+    async fn collect(&self, state: &State, collection: &Collection) -> Result<(), RestError> {
+        let payload = self.fetch(state, collection).await?;
+        self.emit(state, payload).await
+    }
+}
+
+// ECE responses several collectors need, fetched once per collection by the
+// first collector to ask. A failed fetch is tried again by the next one.
+#[derive(Default)]
+pub struct Collection {
+    allocators: OnceCell<Arc<allocator::AllocatorsRoot>>,
+    #[cfg(feature = "deployments")]
+    deployments: OnceCell<Arc<deployment::DeploymentsRoot>>,
+    #[cfg(feature = "deployments")]
+    placements: OnceCell<Arc<Placements>>,
+}
+
+impl Collection {
+    pub async fn allocators(
+        &self,
+        state: &State,
+    ) -> Result<Arc<allocator::AllocatorsRoot>, RestError> {
+        self.allocators
+            .get_or_try_init(|| async { state.get_json(ALLOCATORS_PATH).await.map(Arc::new) })
+            .await
+            .cloned()
+    }
+
+    #[cfg(feature = "deployments")]
+    pub async fn deployments(
+        &self,
+        state: &State,
+    ) -> Result<Arc<deployment::DeploymentsRoot>, RestError> {
+        self.deployments
+            .get_or_try_init(|| async {
+                let query = state.deployment_query.as_ref().clone();
+                let body = state.post_json(DEPLOYMENTS_SEARCH_PATH, query).await;
+                body.map(Arc::new)
+            })
+            .await
+            .cloned()
+    }
+
+    // Where each instance runs, left unknown when the allocators can't be fetched
+    #[cfg(feature = "deployments")]
+    pub async fn placements(&self, state: &State) -> Arc<Placements> {
+        self.placements
+            .get_or_init(|| async {
+                match self.allocators(state).await {
+                    Ok(body) => Arc::new(Placements::new(&body, state.allocator_label)),
+                    Err(e) => {
+                        log::warn!(
+                            "{{\"msg\": \"unable to place instances on allocators\", \"error\": {}}}",
+                            e
+                        );
+                        Arc::new(Placements::default())
+                    }
+                }
+            })
+            .await
+            .clone()
+    }
+}
+
+// Collectors available to --collectors, run in registration order
+#[derive(Default)]
+pub struct Registry {
+    collectors: Vec<Box<dyn Collect>>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_list().entries(self.names()).finish()
+    }
+}
+
+impl Registry {
+    pub fn builtin() -> Self {
+        let mut registry = Registry::default();
+        registry.register(allocators::Allocators);
+        registry.register(proxies::Proxies);
+        registry.register(elasticsearch_clusters::ElasticsearchClusters);
+        registry.register(kibana_clusters::KibanaClusters);
+        #[cfg(feature = "deployments")]
+        {
+            registry.register(deployments::Deployments);
+            registry.register(remote_clusters::RemoteClusters);
+            registry.register(endpoints::Endpoints);
+            registry.register(cluster_health::ClusterHealth);
+            registry.register(shard_allocation::Shards);
+            registry.register(jvm_heap::Heap);
+            registry.register(indices::Indices);
+            registry.register(slm::Slm);
+            registry.register(ilm::Ilm);
+        }
+        registry
+    }
+
+    pub fn register(&mut self, collector: impl Collector + 'static) {
+        self.collectors.push(Box::new(collector));
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.collectors.iter().map(|c| c.name()).collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn Collect> {
+        self.collectors
+            .iter()
+            .find(|c| c.name() == name)
+            .map(|c| c.as_ref())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Collect> {
+        self.collectors.iter().map(|c| c.as_ref())
+    }
+}
@@ -0,0 +1,801 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+
+use crate::allocator;
+use crate::collector::{Collection, Collector, BYTES_PER_MB};
+use crate::config::AllocatorLabel;
+#[cfg(feature = "cost")]
+use crate::cost::{self, CostModel};
+use crate::error::Error as RestError;
+use crate::names::Names;
+use crate::state::{State, ALLOCATORS_PATH, DISCONNECTED_KEY};
+use crate::template::{self, LabelTemplate};
+
+pub struct Allocators;
+
+#[async_trait]
+impl Collector for Allocators {
+    type Payload = Arc<allocator::AllocatorsRoot>;
+
+    fn name(&self) -> &str {
+        "allocators"
+    }
+
+    fn path(&self) -> &str {
+        ALLOCATORS_PATH
+    }
+
+    // The platform is labelled even when the allocators can't be fetched
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        if let Some(version) = state.platform_version().await {
+            metrics::gauge!("ece_platform_info", 1f64, "version" => version);
+        }
+        collection.allocators(state).await
+    }
+
+    async fn emit(&self, state: &State, body: Self::Payload) -> Result<(), RestError> {
+        log::debug!("{:#?}", body);
+
+        #[cfg(feature = "cost")]
+        let costs = state
+            .instance_costs
+            .lock()
+            .unwrap()
+            .collection(&cost_model(state));
+        let options = EmitOptions {
+            #[cfg(feature = "cost")]
+            costs: costs.clone(),
+            mb_metrics: state.mb_metrics,
+            allocator_label: state.allocator_label,
+            aggregate_only: state.aggregate_only,
+            tag_metrics: state.tag_metrics.clone(),
+            instance_labels: state.instance_labels.clone(),
+            names: state.names().await,
+        };
+
+        {
+            let mut diagnostics = state.diagnostics.lock().unwrap();
+            diagnostics.zones = body.zones.len();
+            diagnostics.allocators = body.zones.iter().map(|z| z.allocators.len()).sum();
+            diagnostics.instances = body
+                .zones
+                .iter()
+                .flat_map(|z| &z.allocators)
+                .map(|a| a.instances.len())
+                .sum();
+            let allocators = || body.zones.iter().flat_map(|z| &z.allocators);
+            diagnostics.allocators_unhealthy = allocators()
+                .filter(|a| !a.status.connected || !a.status.healthy)
+                .count();
+            diagnostics.memory_total_bytes = allocators()
+                .map(|a| a.capacity.memory.total as f64 * BYTES_PER_MB)
+                .sum::<f64>() as u64;
+            diagnostics.memory_used_bytes = allocators()
+                .map(|a| a.capacity.memory.used as f64 * BYTES_PER_MB)
+                .sum::<f64>() as u64;
+            #[cfg(feature = "cost")]
+            {
+                let instances = allocators().flat_map(|a| &a.instances);
+                diagnostics.monthly_cost = Some(options.costs.monthly_cost(instances));
+            }
+        }
+
+        let transitions = {
+            let mut events = state.health_events.lock().unwrap();
+            let transitions = events.observe(&body, state.allocator_label);
+            for zone in &body.zones {
+                for allocator in &zone.allocators {
+                    let seconds = events
+                        .disconnected_for(&allocator.allocator_id)
+                        .map(|d| d.as_secs_f64())
+                        .unwrap_or(0.0);
+                    metrics::gauge!(
+                        "ece_allocator_disconnected_seconds",
+                        seconds,
+                        "zone" => zone.zone_id.clone(),
+                        "ip" => state.allocator_label.value(allocator).to_string()
+                    );
+                }
+            }
+            if let Some(snapshot) = &state.snapshot {
+                snapshot.store(
+                    DISCONNECTED_KEY,
+                    serde_json::json!(events.disconnected_since()),
+                );
+            }
+            transitions
+        };
+        state.record_transitions(transitions);
+
+        emit_zone_aggregates(&body);
+        emit_platform_aggregates(&body, state.aggregate_only, state.mb_metrics);
+
+        // Spread the allocators over a set of worker threads, as emitting
+        // series dominates scrape time on platforms with many instances
+        tokio::task::spawn_blocking(move || {
+            let allocators: Vec<(&str, &allocator::Allocator)> = body
+                .zones
+                .iter()
+                .flat_map(|zone| {
+                    log::debug!("\"Working in zone: {}\"", zone.zone_id);
+                    zone.allocators
+                        .iter()
+                        .map(|allocator| (zone.zone_id.as_str(), allocator))
+                })
+                .collect();
+            let workers = thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1);
+            let chunk_size = allocators.len().div_ceil(workers).max(1);
+            let options = &options;
+            thread::scope(|scope| {
+                for chunk in allocators.chunks(chunk_size) {
+                    scope.spawn(move || {
+                        for (zone_id, allocator) in chunk {
+                            emit_allocator(zone_id, allocator, options);
+                        }
+                    });
+                }
+            });
+        })
+        .await
+        .expect("allocator parsing panicked");
+
+        #[cfg(feature = "cost")]
+        state.instance_costs.lock().unwrap().finish(&costs);
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "cost")]
+fn cost_model(state: &State) -> CostModel {
+    CostModel {
+        eru_cost: state.eru_cost,
+        format: state.cost_format,
+        eru_size_gb: state.eru_size_gb,
+        eru_size_overrides: state.eru_size_overrides.clone(),
+    }
+}
+
+// Emit zone level rollups computed across all allocators in each zone
+fn emit_zone_aggregates(body: &allocator::AllocatorsRoot) {
+    let mut platform_moving = 0;
+    for zone in &body.zones {
+        let labels = [("zone", zone.zone_id.clone())];
+
+        let moving = zone
+            .allocators
+            .iter()
+            .flat_map(|a| &a.instances)
+            .filter(|i| i.moving.unwrap_or(false))
+            .count();
+        platform_moving += moving;
+        metrics::gauge!("ece_instances_moving_total", moving as f64, &labels);
+
+        let used: u64 = zone.allocators.iter().map(|a| a.capacity.memory.used).sum();
+        let total: u64 = zone
+            .allocators
+            .iter()
+            .map(|a| a.capacity.memory.total)
+            .sum();
+        metrics::gauge!(
+            "ece_zone_memory_utilization_ratio",
+            utilization(used, total),
+            &labels
+        );
+
+        // Instances per allocator, to spot allocators crowded with small instances
+        let density: Vec<usize> = zone.allocators.iter().map(|a| a.instances.len()).collect();
+        if !density.is_empty() {
+            let min = density.iter().min().copied().unwrap_or(0);
+            let max = density.iter().max().copied().unwrap_or(0);
+            let avg = density.iter().sum::<usize>() as f64 / density.len() as f64;
+            metrics::gauge!("ece_zone_instance_density_min", min as f64, &labels);
+            metrics::gauge!("ece_zone_instance_density_max", max as f64, &labels);
+            metrics::gauge!("ece_zone_instance_density_avg", avg, &labels);
+        }
+
+        let idle = zone.allocators.iter().filter(|a| is_idle(a)).count();
+        metrics::gauge!("ece_zone_idle_allocators_total", idle as f64, &labels);
+
+        // An instance must fit on a single allocator, so headroom is bounded by the
+        // allocator with the most free memory rather than the zone's free total
+        let max_free = zone
+            .allocators
+            .iter()
+            .filter(|a| a.status.connected && a.status.healthy && !a.status.maintenance_mode)
+            .map(|a| {
+                a.capacity
+                    .memory
+                    .total
+                    .saturating_sub(a.capacity.memory.used)
+            })
+            .max()
+            .unwrap_or(0);
+        metrics::gauge!(
+            "ece_zone_max_placeable_instance_gb",
+            max_free as f64 / 1024.0,
+            &labels
+        );
+    }
+
+    metrics::gauge!(
+        "ece_platform_instances_moving_total",
+        platform_moving as f64
+    );
+}
+
+// Connected, healthy allocators without any instances are decommissioning candidates
+fn is_idle(allocator: &allocator::Allocator) -> bool {
+    allocator.status.connected && allocator.status.healthy && allocator.instances.is_empty()
+}
+
+// Emit deployment and platform level rollups computed across all allocators
+fn emit_platform_aggregates(
+    body: &allocator::AllocatorsRoot,
+    aggregate_only: bool,
+    mb_metrics: bool,
+) {
+    let mut instances: HashMap<(String, String), u64> = HashMap::new();
+    let mut memory: HashMap<(String, String), u64> = HashMap::new();
+    let mut cluster_memory: HashMap<(String, String), u64> = HashMap::new();
+    let mut pending: HashMap<String, u64> = HashMap::new();
+    for instance in body
+        .zones
+        .iter()
+        .flat_map(|zone| &zone.allocators)
+        .flat_map(|allocator| &allocator.instances)
+    {
+        let deployment_id = instance.deployment_id.clone().unwrap_or("null".to_string());
+        *instances
+            .entry((deployment_id.clone(), instance.cluster_type.clone()))
+            .or_default() += 1;
+        // Instances are sized per tier, i.e. per instance configuration
+        let tier = match instance.instance_configuration_id.as_str() {
+            "" => "null".to_string(),
+            id => id.to_string(),
+        };
+        *memory.entry((deployment_id, tier)).or_default() += instance.node_memory;
+        *cluster_memory
+            .entry((instance.cluster_id.clone(), instance.cluster_type.clone()))
+            .or_default() += instance.node_memory;
+
+        let plan_pending = match &instance.plans_info {
+            Some(plans_info) => plans_info.pending,
+            None => false,
+        };
+        let count = pending.entry(instance.cluster_type.clone()).or_default();
+        if plan_pending {
+            *count += 1;
+        }
+    }
+
+    if !aggregate_only {
+        for ((deployment_id, cluster_type), count) in instances {
+            let labels = [
+                ("deployment_id", deployment_id),
+                ("cluster_type", cluster_type),
+            ];
+            metrics::gauge!("ece_deployment_instances", count as f64, &labels);
+        }
+        for ((deployment_id, tier), total) in memory {
+            let labels = [("deployment_id", deployment_id), ("tier", tier)];
+            if mb_metrics {
+                metrics::gauge!("ece_deployment_memory_total", total as f64, &labels);
+            }
+            metrics::gauge!(
+                "ece_deployment_memory_total_bytes",
+                total as f64 * BYTES_PER_MB,
+                &labels
+            );
+        }
+        for ((cluster_id, cluster_type), total) in cluster_memory {
+            let labels = [("cluster_id", cluster_id), ("cluster_type", cluster_type)];
+            if mb_metrics {
+                metrics::gauge!("ece_cluster_memory_total", total as f64, &labels);
+            }
+            metrics::gauge!(
+                "ece_cluster_memory_total_bytes",
+                total as f64 * BYTES_PER_MB,
+                &labels
+            );
+        }
+    }
+
+    metrics::gauge!(
+        "ece_plans_pending_total",
+        pending.values().sum::<u64>() as f64
+    );
+    for (cluster_type, count) in pending {
+        let labels = [("cluster_type", cluster_type)];
+        metrics::gauge!(
+            "ece_cluster_type_plans_pending_total",
+            count as f64,
+            &labels
+        );
+    }
+
+    let allocators = || body.zones.iter().flat_map(|zone| &zone.allocators);
+    let unhealthy = allocators().filter(|a| !a.status.healthy).count();
+    let disconnected = allocators().filter(|a| !a.status.connected).count();
+    metrics::gauge!("ece_allocators_unhealthy_total", unhealthy as f64);
+    metrics::gauge!("ece_allocators_disconnected_total", disconnected as f64);
+}
+
+// Ratio of used to total memory, between 0 and 1
+fn utilization(used: u64, total: u64) -> f64 {
+    match total {
+        0 => 0.0,
+        _ => (used as f64 / total as f64).min(1.0),
+    }
+}
+
+// Settings shared by the allocator emitting workers
+#[derive(Clone, Debug)]
+struct EmitOptions {
+    #[cfg(feature = "cost")]
+    costs: cost::Collection,
+    mb_metrics: bool,
+    allocator_label: AllocatorLabel,
+    aggregate_only: bool,
+    tag_metrics: Arc<HashMap<String, String>>,
+    instance_labels: Arc<Vec<LabelTemplate>>,
+    names: Arc<Names>,
+}
+
+// Emit all series for a single allocator and the instances it hosts
+fn emit_allocator(zone_id: &str, allocator: &allocator::Allocator, options: &EmitOptions) {
+    log::debug!("\"Working in allocator: {}\"", allocator.public_hostname);
+
+    let identity = options.allocator_label.value(allocator);
+
+    // Generate a set of standard labels for allocator, setting aside tags
+    // that are exported as gauges instead
+    let mut alloc_tags = Vec::new();
+    let mut tag_values = Vec::new();
+    for tag in &allocator.metadata {
+        if let Some(metric) = options.tag_metrics.get(&tag.key) {
+            match tag.value.trim().parse::<f64>() {
+                Ok(value) => tag_values.push((metric.clone(), value)),
+                Err(_) => log::debug!(
+                    "\"Tag {} on {} is not numeric: {}\"",
+                    tag.key,
+                    allocator.allocator_id,
+                    tag.value
+                ),
+            }
+            continue;
+        }
+        let key = tag.key.to_owned();
+        alloc_tags.push((key, tag.value.clone()))
+    }
+
+    let mut labels = vec![
+        ("zone".to_string(), zone_id.to_string()),
+        ("ip".to_string(), identity.to_owned()),
+        ("host_ip".to_string(), allocator.host_ip.to_owned()),
+        (
+            "connected".to_string(),
+            allocator.status.connected.to_string(),
+        ),
+        ("healthy".to_string(), allocator.status.healthy.to_string()),
+        (
+            "maintenance".to_string(),
+            allocator.status.maintenance_mode.to_string(),
+        ),
+    ];
+
+    // Include allocator tags
+    for tag in &alloc_tags {
+        labels.push(tag.clone())
+    }
+
+    metrics::gauge!("ece_allocator_info", 1f64, &labels);
+
+    let mut labels = vec![
+        ("zone".to_string(), zone_id.to_string()),
+        ("ip".to_string(), identity.to_owned()),
+    ];
+
+    // Include allocator tags
+    for tag in &alloc_tags {
+        labels.push(tag.clone())
+    }
+
+    for (metric, value) in tag_values {
+        metrics::gauge!(metric, value, &labels);
+    }
+
+    let memory = &allocator.capacity.memory;
+    let available = memory.total.saturating_sub(memory.used);
+    if options.mb_metrics {
+        metrics::gauge!("ece_allocator_memory_used", memory.used as f64, &labels);
+        metrics::gauge!("ece_allocator_memory_total", memory.total as f64, &labels);
+        metrics::gauge!("ece_allocator_memory_available", available as f64, &labels);
+    }
+    metrics::gauge!(
+        "ece_allocator_memory_used_bytes",
+        memory.used as f64 * BYTES_PER_MB,
+        &labels
+    );
+    metrics::gauge!(
+        "ece_allocator_memory_total_bytes",
+        memory.total as f64 * BYTES_PER_MB,
+        &labels
+    );
+    metrics::gauge!(
+        "ece_allocator_memory_available_bytes",
+        available as f64 * BYTES_PER_MB,
+        &labels
+    );
+    metrics::gauge!(
+        "ece_allocator_memory_utilization_ratio",
+        utilization(
+            allocator.capacity.memory.used,
+            allocator.capacity.memory.total
+        ),
+        &labels
+    );
+    if let Some(cpu) = &allocator.capacity.cpu {
+        metrics::gauge!("ece_allocator_cpu_total", cpu.total, &labels);
+        metrics::gauge!("ece_allocator_cpu_used", cpu.used, &labels);
+        metrics::gauge!(
+            "ece_allocator_cpu_available",
+            (cpu.total - cpu.used).max(0.0),
+            &labels
+        );
+    }
+    metrics::gauge!(
+        "ece_allocator_instances_total",
+        allocator.instances.len() as f64,
+        &labels
+    );
+    metrics::gauge!(
+        "ece_allocator_instance_density",
+        allocator.instances.len() as f64,
+        &labels
+    );
+    // Reported usage should equal the sum of its instances; a gap usually means
+    // stale ZooKeeper state or ghost instances
+    let instance_memory: u64 = allocator.instances.iter().map(|i| i.node_memory).sum();
+    let discrepancy = memory.used as f64 - instance_memory as f64;
+    metrics::gauge!(
+        "ece_allocator_memory_discrepancy_bytes",
+        discrepancy * BYTES_PER_MB,
+        &labels
+    );
+    metrics::gauge!(
+        "ece_allocator_memory_mismatch",
+        if discrepancy == 0.0 { 0f64 } else { 1f64 },
+        &labels
+    );
+    // An allocator listed under a zone other than its own points at stale platform state
+    let mut zone_labels = labels.clone();
+    zone_labels.push(("allocator_zone".to_string(), allocator.zone_id.clone()));
+    metrics::gauge!(
+        "ece_allocator_zone_mismatch",
+        if allocator.zone_id == zone_id {
+            0f64
+        } else {
+            1f64
+        },
+        &zone_labels
+    );
+    if is_idle(allocator) {
+        metrics::gauge!("ece_allocator_idle", 1f64, &labels);
+    }
+
+    // Roll instances up by configuration, the low cardinality view of what runs where
+    let mut configurations: HashMap<&str, (u64, u64)> = HashMap::new();
+    for instance in &allocator.instances {
+        let entry = configurations
+            .entry(&instance.instance_configuration_id)
+            .or_default();
+        entry.0 += 1;
+        entry.1 += instance.node_memory;
+    }
+    for (configuration_id, (count, memory)) in configurations {
+        let mut labels = labels.clone();
+        labels.push(("configuration_id".to_string(), configuration_id.to_string()));
+        labels.push((
+            "configuration_name".to_string(),
+            options
+                .names
+                .configuration(configuration_id)
+                .unwrap_or("null")
+                .to_string(),
+        ));
+        metrics::gauge!(
+            "ece_allocator_configuration_instances",
+            count as f64,
+            &labels
+        );
+        metrics::gauge!(
+            "ece_allocator_configuration_node_memory_bytes",
+            memory as f64 * BYTES_PER_MB,
+            &labels
+        );
+    }
+
+    if options.aggregate_only {
+        return;
+    }
+
+    for instance in &allocator.instances {
+        let deployment_name = options
+            .names
+            .deployment_of(instance.deployment_id.as_deref(), &instance.cluster_id);
+
+        // Many instances come back without a cluster name, so fall back to the deployment name
+        let cluster_name = instance
+            .cluster_name
+            .clone()
+            .unwrap_or(deployment_name.to_string());
+        let cluster_healthy = match instance.cluster_healthy {
+            Some(t) => t.to_string(),
+            None => "null".to_string(),
+        };
+        log::debug!("\"Working in instance: {}\"", &cluster_name);
+
+        // Allocator tags plus the labels rendered from --instance-label templates
+        let context = template::Context {
+            zone: zone_id,
+            allocator,
+            instance,
+            cluster_name: &cluster_name,
+            deployment_name,
+        };
+        let mut instance_tags = alloc_tags.clone();
+        for template in options.instance_labels.iter() {
+            instance_tags.retain(|(key, _)| *key != template.name);
+            instance_tags.push((template.name.clone(), template.render(&context)));
+        }
+
+        let mut labels = vec![
+            ("zone".to_string(), zone_id.to_string()),
+            ("ip".to_string(), identity.to_owned()),
+            ("name".to_string(), cluster_name.clone()),
+            (
+                "cluster_type".to_string(),
+                instance.cluster_type.to_string(),
+            ),
+            ("cluster_id".to_string(), instance.cluster_id.to_owned()),
+            (
+                "instance_name".to_string(),
+                instance.instance_name.to_owned(),
+            ),
+            (
+                "configuration_id".to_string(),
+                instance.instance_configuration_id.to_owned(),
+            ),
+            (
+                "configuration_name".to_string(),
+                options
+                    .names
+                    .configuration(&instance.instance_configuration_id)
+                    .unwrap_or("null")
+                    .to_string(),
+            ),
+            (
+                "deployment_id".to_string(),
+                instance.deployment_id.clone().unwrap_or("null".to_string()),
+            ),
+            ("deployment_name".to_string(), deployment_name.to_string()),
+            (
+                "healthy".to_string(),
+                instance.healthy.unwrap_or(false).to_string(),
+            ),
+            ("cluster_healthy".to_string(), cluster_healthy.to_owned()),
+            (
+                "moving".to_string(),
+                instance.moving.unwrap_or(false).to_string(),
+            ),
+        ];
+
+        // Include allocator tags
+        for tag in &instance_tags {
+            labels.push(tag.clone())
+        }
+        metrics::gauge!("ece_allocator_instance_info", 1f64, &labels);
+
+        let mut labels = vec![
+            ("zone".to_string(), zone_id.to_string()),
+            ("ip".to_string(), identity.to_owned()),
+            ("name".to_string(), cluster_name.clone()),
+            (
+                "cluster_type".to_string(),
+                instance.cluster_type.to_string(),
+            ),
+            ("cluster_id".to_string(), instance.cluster_id.to_owned()),
+        ];
+        // Include allocator tags
+        for tag in &instance_tags {
+            labels.push(tag.clone())
+        }
+        // Locate the node itself on the allocator
+        let mut node_labels = labels.clone();
+        node_labels.push((
+            "instance_name".to_string(),
+            instance.instance_name.to_owned(),
+        ));
+        if options.mb_metrics {
+            metrics::gauge!(
+                "ece_allocator_instance_node_memory",
+                instance.node_memory as f64,
+                &node_labels
+            );
+        }
+        metrics::gauge!(
+            "ece_allocator_instance_node_memory_bytes",
+            instance.node_memory as f64 * BYTES_PER_MB,
+            &node_labels
+        );
+
+        #[cfg(feature = "cost")]
+        options.costs.emit(instance, &labels, &node_labels);
+
+        if let Some(plans_info) = &instance.plans_info {
+            let mut labels = vec![
+                ("zone".to_string(), zone_id.to_string()),
+                ("allocator".to_string(), identity.to_owned()),
+                ("name".to_string(), cluster_name.clone()),
+                ("pending".to_string(), plans_info.pending.to_string()),
+                (
+                    "version".to_string(),
+                    plans_info.version.clone().unwrap_or("0".to_string()),
+                ),
+                (
+                    "cluster_type".to_string(),
+                    instance.cluster_type.to_string(),
+                ),
+                (
+                    "zone_count".to_string(),
+                    plans_info.zone_count.unwrap_or(0u64).to_string(),
+                ),
+            ];
+            // Include allocator tags
+            for tag in &instance_tags {
+                labels.push(tag.clone())
+            }
+            metrics::gauge!("ece_allocator_instance_plan", 1f64, &labels);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{collect, fixture, gauge, state, MockApi};
+    use crate::snapshot::Snapshot;
+    use crate::state::{DEPLOYMENTS_PATH, INSTANCE_CONFIGURATIONS_PATH, PLATFORM_PATH};
+    use hyper::{Method, StatusCode};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[tokio::test]
+    async fn parse_allocators_emits_capacity_and_instances() {
+        let api = Arc::new(
+            MockApi::new()
+                .respond(PLATFORM_PATH, StatusCode::OK, fixture!("platform"))
+                .respond(ALLOCATORS_PATH, StatusCode::OK, fixture!("allocators"))
+                .respond(DEPLOYMENTS_PATH, StatusCode::OK, fixture!("deployments"))
+                .respond(
+                    INSTANCE_CONFIGURATIONS_PATH,
+                    StatusCode::OK,
+                    fixture!("instance_configurations"),
+                ),
+        );
+        let state = state(api.clone(), &[]).await;
+        collect(&state, "allocators").await.unwrap();
+
+        assert_eq!(
+            gauge("ece_platform_info", &[("version", "3.6.2")]),
+            Some(1.0)
+        );
+        let alloc1 = [("zone", "zone-1"), ("ip", "alloc1.example.com")];
+        assert_eq!(
+            gauge("ece_allocator_memory_used_bytes", &alloc1),
+            Some(6144.0 * BYTES_PER_MB)
+        );
+        assert_eq!(gauge("ece_allocator_cpu_available", &alloc1), Some(9.5));
+        assert_eq!(gauge("ece_allocator_instance_density", &alloc1), Some(2.0));
+        // The 3.x payload omits settings and build info, and names product_type
+        let alloc2 = [("zone", "zone-1"), ("ip", "alloc2.example.com")];
+        assert_eq!(gauge("ece_allocator_memory_used_bytes", &alloc2), Some(0.0));
+        // alloc3 is listed under zone-2 but reports itself in zone-3
+        assert_eq!(gauge("ece_allocator_zone_mismatch", &alloc1), Some(0.0));
+        let alloc3 = [("ip", "alloc3.example.com"), ("allocator_zone", "zone-3")];
+        assert_eq!(gauge("ece_allocator_zone_mismatch", &alloc3), Some(1.0));
+
+        // Instances of the same tier are summed across allocators and zones
+        let d1 = [("deployment_id", "d1"), ("tier", "data.default")];
+        assert_eq!(gauge("ece_deployment_memory_total", &d1), Some(8192.0));
+        let node = [
+            ("ip", "alloc3.example.com"),
+            ("instance_name", "instance-0000000002"),
+        ];
+        assert_eq!(
+            gauge("ece_allocator_instance_node_memory_bytes", &node),
+            Some(4096.0 * BYTES_PER_MB)
+        );
+        let c1 = [("cluster_id", "c1"), ("cluster_type", "elasticsearch")];
+        assert_eq!(gauge("ece_cluster_memory_total", &c1), Some(8192.0));
+        let kibana = [
+            ("cluster_id", "k1"),
+            ("deployment_name", "logging"),
+            ("configuration_name", "Kibana instances"),
+        ];
+        assert_eq!(gauge("ece_allocator_instance_info", &kibana), Some(1.0));
+
+        let diagnostics = state.diagnostics.lock().unwrap();
+        assert_eq!(diagnostics.zones, 2);
+        assert_eq!(diagnostics.allocators, 3);
+        assert_eq!(diagnostics.instances, 3);
+        assert!(api
+            .requests()
+            .contains(&(Method::GET, DEPLOYMENTS_PATH.to_string())));
+    }
+
+    #[tokio::test]
+    async fn disconnected_seconds_survive_a_restart() {
+        // zone-2's allocator is disconnected; renamed so no other test emits its series
+        let allocators = fixture!("allocators").replace("\"zone-2\"", "\"zone-restored\"");
+        let api = Arc::new(
+            MockApi::new()
+                .respond(PLATFORM_PATH, StatusCode::OK, fixture!("platform"))
+                .respond(
+                    ALLOCATORS_PATH,
+                    StatusCode::OK,
+                    Box::leak(allocators.into_boxed_str()),
+                )
+                .respond(DEPLOYMENTS_PATH, StatusCode::OK, fixture!("deployments"))
+                .respond(
+                    INSTANCE_CONFIGURATIONS_PATH,
+                    StatusCode::OK,
+                    fixture!("instance_configurations"),
+                ),
+        );
+        let file = std::env::temp_dir().join(format!("ece-snapshot-{}.json", std::process::id()));
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            - 600;
+        let persisted = serde_json::json!({
+            DISCONNECTED_KEY: {"fetched": since, "value": {"10.0.0.3": since}}
+        });
+        std::fs::write(&file, persisted.to_string()).unwrap();
+
+        let state = state(api, &["--snapshot-file", file.to_str().unwrap()]).await;
+        collect(&state, "allocators").await.unwrap();
+        let labels = [("zone", "zone-restored"), ("ip", "alloc3.example.com")];
+        let seconds = gauge("ece_allocator_disconnected_seconds", &labels).unwrap();
+        assert!((600.0..660.0).contains(&seconds));
+
+        // Still disconnected, so the next run picks up the same time again
+        state.flush_snapshot().await;
+        let restored = Snapshot::load(file.to_str().unwrap())
+            .restored::<HashMap<String, u64>>(DISCONNECTED_KEY)
+            .unwrap();
+        assert_eq!(restored.0.get("10.0.0.3"), Some(&since));
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parse_allocators_reports_api_errors() {
+        let api = Arc::new(MockApi::new().respond(
+            ALLOCATORS_PATH,
+            StatusCode::INTERNAL_SERVER_ERROR,
+            r#"{"errors": [{"code": "root.unexpected_error", "message": "boom"}]}"#,
+        ));
+        let state = state(api, &[]).await;
+        let error = collect(&state, "allocators").await.unwrap_err();
+        assert!(error.is_server_error());
+    }
+}
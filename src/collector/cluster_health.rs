@@ -0,0 +1,83 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_elasticsearch, ResourceLookup};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type ClusterHealthLookup = ResourceLookup<deployment::ClusterHealth>;
+
+pub struct ClusterHealth;
+
+#[async_trait]
+impl Collector for ClusterHealth {
+    type Payload = Vec<ClusterHealthLookup>;
+
+    fn name(&self) -> &str {
+        "cluster_health"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // _cluster/health of each Elasticsearch resource, asked through the ECE proxy
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_elasticsearch(
+            state,
+            collection,
+            |state, deployment_id, ref_id| async move {
+                let path = format!(
+                    "{}/{}/elasticsearch/{}/proxy/_cluster/health",
+                    DEPLOYMENTS_PATH, deployment_id, ref_id
+                );
+                state.get_json(&path).await
+            },
+        )
+        .await
+    }
+
+    // Cluster health as Elasticsearch itself sees it, which can be yellow or red
+    // while ECE reports every instance healthy
+    async fn emit(&self, _state: &State, lookups: Self::Payload) -> Result<(), RestError> {
+        emit_lookups(lookups, "cluster health", |resource, health| {
+            for color in ["green", "yellow", "red"] {
+                let mut labels = resource.labels().to_vec();
+                labels.push(("color", color.to_string()));
+                let value = if health.status == color { 1f64 } else { 0f64 };
+                metrics::gauge!("ece_deployment_cluster_health_status", value, &labels);
+            }
+
+            let labels = resource.labels();
+            metrics::gauge!(
+                "ece_deployment_cluster_health_nodes",
+                health.number_of_nodes as f64,
+                &labels
+            );
+            metrics::gauge!(
+                "ece_deployment_cluster_health_relocating_shards",
+                health.relocating_shards as f64,
+                &labels
+            );
+            metrics::gauge!(
+                "ece_deployment_cluster_health_initializing_shards",
+                health.initializing_shards as f64,
+                &labels
+            );
+            metrics::gauge!(
+                "ece_deployment_cluster_health_unassigned_shards",
+                health.unassigned_shards as f64,
+                &labels
+            );
+        })
+    }
+}
@@ -0,0 +1,68 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_SEARCH_PATH};
+
+pub struct Deployments;
+
+#[async_trait]
+impl Collector for Deployments {
+    type Payload = Arc<deployment::DeploymentsRoot>;
+
+    fn name(&self) -> &str {
+        "deployments"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        collection.deployments(state).await
+    }
+
+    async fn emit(&self, _state: &State, body: Self::Payload) -> Result<(), RestError> {
+        log::debug!("{:#?}", body);
+
+        for deployment in &body.deployments {
+            log::debug!("\"Working on deployment: {}\"", deployment.id);
+            for (kind, resource) in deployment.resources.monitored() {
+                let labels = [
+                    ("deployment_id", deployment.id.clone()),
+                    ("deployment_name", deployment.name.clone()),
+                    ("kind", kind.to_string()),
+                    ("ref_id", resource.ref_id.clone()),
+                    ("resource_id", resource.id.clone()),
+                ];
+                let healthy = if resource.info.healthy { 1f64 } else { 0f64 };
+                metrics::gauge!("ece_deployment_resource_healthy", healthy, &labels);
+
+                let labels = [
+                    ("deployment_id", deployment.id.clone()),
+                    ("deployment_name", deployment.name.clone()),
+                    ("kind", kind.to_string()),
+                    ("ref_id", resource.ref_id.clone()),
+                    ("resource_id", resource.id.clone()),
+                    ("status", resource.info.status.clone()),
+                    (
+                        "version",
+                        resource.version(kind).unwrap_or("null").to_string(),
+                    ),
+                ];
+                metrics::gauge!("ece_deployment_resource_info", 1f64, &labels);
+            }
+        }
+        Ok(())
+    }
+}
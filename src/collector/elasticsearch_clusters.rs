@@ -0,0 +1,294 @@
+use async_trait::async_trait;
+
+use crate::collector::{Collection, Collector, BYTES_PER_MB};
+use crate::elasticsearch;
+use crate::error::Error as RestError;
+use crate::state::{State, ELASTICSEARCH_PATH};
+
+pub struct ElasticsearchClusters;
+
+#[async_trait]
+impl Collector for ElasticsearchClusters {
+    type Payload = elasticsearch::ElasticsearchClustersRoot;
+
+    fn name(&self) -> &str {
+        "elasticsearch"
+    }
+
+    fn path(&self) -> &str {
+        ELASTICSEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    async fn fetch(
+        &self,
+        state: &State,
+        _collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        state.get_json(ELASTICSEARCH_PATH).await
+    }
+
+    async fn emit(&self, state: &State, body: Self::Payload) -> Result<(), RestError> {
+        log::debug!("{:#?}", body);
+        let names = state.names().await;
+
+        for cluster in &body.elasticsearch_clusters {
+            log::debug!("\"Working on cluster: {}\"", cluster.cluster_id);
+            let version = cluster
+                .plan()
+                .and_then(|plan| plan["elasticsearch"]["version"].as_str())
+                .unwrap_or("null");
+            let labels = [
+                ("cluster_id", cluster.cluster_id.clone()),
+                ("name", cluster.cluster_name.clone()),
+                (
+                    "deployment_id",
+                    cluster.deployment_id.clone().unwrap_or("null".to_string()),
+                ),
+                (
+                    "deployment_name",
+                    names
+                        .deployment_of(cluster.deployment_id.as_deref(), &cluster.cluster_id)
+                        .to_string(),
+                ),
+                ("status", cluster.status.clone()),
+                ("healthy", cluster.healthy.to_string()),
+                ("version", version.to_string()),
+            ];
+            metrics::gauge!("ece_elasticsearch_cluster_info", 1f64, &labels);
+
+            let color = cluster.color();
+            for state in ["green", "yellow", "red", "unknown"] {
+                let labels = [
+                    ("cluster_id", cluster.cluster_id.clone()),
+                    ("name", cluster.cluster_name.clone()),
+                    ("color", state.to_string()),
+                ];
+                let value = if state == color { 1f64 } else { 0f64 };
+                metrics::gauge!("ece_elasticsearch_cluster_status", value, &labels);
+            }
+
+            let labels = [
+                ("cluster_id", cluster.cluster_id.clone()),
+                ("name", cluster.cluster_name.clone()),
+            ];
+            if let Some(shard_info) = cluster
+                .elasticsearch
+                .as_ref()
+                .and_then(|e| e.shard_info.as_ref())
+            {
+                let available: u64 = shard_info
+                    .available_shards
+                    .iter()
+                    .map(|s| s.shard_count)
+                    .sum();
+                let unavailable: u64 = shard_info
+                    .unavailable_shards
+                    .iter()
+                    .map(|s| s.shard_count)
+                    .sum();
+                let replicas: u64 = shard_info
+                    .unavailable_replicas
+                    .iter()
+                    .map(|r| r.replica_count)
+                    .sum();
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_shards_available",
+                    available as f64,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_shards_unavailable",
+                    unavailable as f64,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_replicas_unavailable",
+                    replicas as f64,
+                    &labels
+                );
+            }
+
+            if let Some(topology) = &cluster.topology {
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_instances",
+                    topology.instances.len() as f64,
+                    &labels
+                );
+            }
+            emit_version_drift(&cluster.cluster_id, cluster.topology.as_ref(), version);
+
+            // Desired topology from the current plan, one series per instance configuration
+            if let Some(elements) = cluster
+                .plan()
+                .and_then(|plan| plan["cluster_topology"].as_array())
+            {
+                for element in elements {
+                    let size = element["size"]["value"]
+                        .as_u64()
+                        .or_else(|| element["memory_per_node"].as_u64())
+                        .unwrap_or(0);
+                    let labels = [
+                        ("cluster_id", cluster.cluster_id.clone()),
+                        ("name", cluster.cluster_name.clone()),
+                        (
+                            "configuration_id",
+                            element["instance_configuration_id"]
+                                .as_str()
+                                .unwrap_or("null")
+                                .to_string(),
+                        ),
+                        (
+                            "zone_count",
+                            element["zone_count"].as_u64().unwrap_or(0).to_string(),
+                        ),
+                    ];
+                    metrics::gauge!(
+                        "ece_elasticsearch_cluster_topology_size",
+                        size as f64,
+                        &labels
+                    );
+                }
+            }
+
+            // Desired against actual per tier, a lasting gap meaning the plan never converged
+            for (configuration_id, tier) in cluster.tiers() {
+                let labels = [
+                    ("cluster_id", cluster.cluster_id.clone()),
+                    ("name", cluster.cluster_name.clone()),
+                    ("configuration_id", configuration_id),
+                ];
+                if state.mb_metrics {
+                    metrics::gauge!(
+                        "ece_elasticsearch_cluster_tier_desired_memory",
+                        tier.desired_memory as f64,
+                        &labels
+                    );
+                    metrics::gauge!(
+                        "ece_elasticsearch_cluster_tier_actual_memory",
+                        tier.actual_memory as f64,
+                        &labels
+                    );
+                }
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_tier_desired_memory_bytes",
+                    tier.desired_memory as f64 * BYTES_PER_MB,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_tier_actual_memory_bytes",
+                    tier.actual_memory as f64 * BYTES_PER_MB,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_tier_desired_zones",
+                    tier.desired_zones as f64,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_elasticsearch_cluster_tier_actual_zones",
+                    tier.actual_zones.len() as f64,
+                    &labels
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// An upgrade moves instances to the plan's target version one by one, so any
+// instance running another version is still to be upgraded
+pub fn emit_version_drift(
+    cluster_id: &str,
+    topology: Option<&elasticsearch::Topology>,
+    target: &str,
+) {
+    // Without a plan there's no target to compare against
+    let Some(topology) = topology.filter(|_| target != "null") else {
+        return;
+    };
+    let target = parse_version(target);
+    let behind = topology
+        .instances
+        .iter()
+        .filter_map(|instance| instance.service_version.as_deref())
+        .filter(|version| parse_version(version) != target)
+        .count();
+    let labels = [("cluster_id", cluster_id.to_string())];
+    metrics::gauge!("ece_instance_version_drift", behind as f64, &labels);
+}
+
+// Numeric parts of a version such as 7.17.0, ignoring any suffix like -SNAPSHOT
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|part| {
+            let digits: String = part.chars().take_while(|c| c.is_ascii_digit()).collect();
+            digits.parse().unwrap_or(0)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{collect, fixture, gauge, state, MockApi};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn parse_elasticsearch_clusters_reports_topology_gaps() {
+        let api = Arc::new(MockApi::new().respond(
+            ELASTICSEARCH_PATH,
+            StatusCode::OK,
+            fixture!("elasticsearch"),
+        ));
+        let state = state(api, &[]).await;
+        collect(&state, "elasticsearch").await.unwrap();
+
+        let converged = [("cluster_id", "c1"), ("configuration_id", "data.default")];
+        assert_eq!(
+            gauge("ece_elasticsearch_cluster_tier_desired_memory", &converged),
+            Some(8192.0)
+        );
+        assert_eq!(
+            gauge("ece_elasticsearch_cluster_tier_actual_memory", &converged),
+            Some(8192.0)
+        );
+        // The highstorage tier was grown to two zones but only one instance runs
+        let growing = [
+            ("cluster_id", "c1"),
+            ("configuration_id", "data.highstorage"),
+        ];
+        assert_eq!(
+            gauge(
+                "ece_elasticsearch_cluster_tier_desired_memory_bytes",
+                &growing
+            ),
+            Some(16384.0 * BYTES_PER_MB)
+        );
+        assert_eq!(
+            gauge(
+                "ece_elasticsearch_cluster_tier_actual_memory_bytes",
+                &growing
+            ),
+            Some(4096.0 * BYTES_PER_MB)
+        );
+        assert_eq!(
+            gauge("ece_elasticsearch_cluster_tier_desired_zones", &growing),
+            Some(2.0)
+        );
+        assert_eq!(
+            gauge("ece_elasticsearch_cluster_tier_actual_zones", &growing),
+            Some(1.0)
+        );
+        // c1 is half way through an upgrade from 7.16.0 to its plan's 7.17.0
+        assert_eq!(
+            gauge("ece_instance_version_drift", &[("cluster_id", "c1")]),
+            Some(1.0)
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use async_trait::async_trait;
+use hyper::header::USER_AGENT;
+use hyper::{Request, StatusCode};
+use std::time::{Duration, Instant};
+
+use crate::collector::{Collection, Collector};
+use crate::error::Error as RestError;
+use crate::https::RequestBody;
+use crate::state::{State, DEPLOYMENTS_SEARCH_PATH};
+
+// Outcome of probing one resource's public URL: the status it answered with,
+// if any, and how long that took
+pub struct EndpointProbe {
+    deployment_id: String,
+    deployment_name: String,
+    kind: &'static str,
+    ref_id: String,
+    status: Option<StatusCode>,
+    elapsed: Duration,
+}
+
+pub struct Endpoints;
+
+#[async_trait]
+impl Collector for Endpoints {
+    type Payload = Vec<EndpointProbe>;
+
+    fn name(&self) -> &str {
+        "endpoints"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Probe the public URL of every Elasticsearch and Kibana resource through the
+    // proxies. Only the deployments search can fail the collector.
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        let body = collection.deployments(state).await?;
+
+        let mut tasks = Vec::new();
+        for deployment in &body.deployments {
+            for (kind, resource) in deployment.resources.endpoints() {
+                let Some(url) = resource.url() else {
+                    continue;
+                };
+                let state = state.clone();
+                let url = url.to_string();
+                let deployment_id = deployment.id.clone();
+                let deployment_name = deployment.name.clone();
+                let ref_id = resource.ref_id.clone();
+                tasks.push(tokio::spawn(async move {
+                    let started = Instant::now();
+                    let status = probe(&state, &url).await;
+                    EndpointProbe {
+                        deployment_id,
+                        deployment_name,
+                        kind,
+                        ref_id,
+                        status,
+                        elapsed: started.elapsed(),
+                    }
+                }));
+            }
+        }
+
+        let mut probes = Vec::new();
+        for task in tasks {
+            probes.push(task.await.expect("endpoint probe panicked"));
+        }
+        Ok(probes)
+    }
+
+    // Any answer from the resource counts as up, including a 401 for the missing
+    // credentials. The proxies answer 404 for a resource they can't route to.
+    async fn emit(&self, _state: &State, probes: Self::Payload) -> Result<(), RestError> {
+        for probe in probes {
+            let labels = [
+                ("deployment_id", probe.deployment_id),
+                ("deployment_name", probe.deployment_name),
+                ("kind", probe.kind.to_string()),
+                ("ref_id", probe.ref_id),
+            ];
+            let up = probe
+                .status
+                .is_some_and(|s| s != StatusCode::NOT_FOUND && !s.is_server_error());
+            metrics::gauge!(
+                "ece_deployment_endpoint_up",
+                if up { 1f64 } else { 0f64 },
+                &labels
+            );
+            if probe.status.is_some() {
+                metrics::gauge!(
+                    "ece_deployment_endpoint_response_seconds",
+                    probe.elapsed.as_secs_f64(),
+                    &labels
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// Status of an unauthenticated GET, or None when no response arrived in time
+async fn probe(state: &State, url: &str) -> Option<StatusCode> {
+    let _permit = state
+        .request_slots
+        .acquire()
+        .await
+        .expect("request semaphore closed");
+    let req = Request::get(url)
+        .header(USER_AGENT, state.user_agent.clone())
+        .body(RequestBody::default());
+    let req = match req {
+        Ok(req) => req,
+        Err(e) => {
+            log::warn!("{{\"url\": \"{}\", \"error\": \"{}\"}}", url, e);
+            return None;
+        }
+    };
+    match tokio::time::timeout(state.probe_timeout, state.prober.request(req)).await {
+        Ok(Ok(response)) => Some(response.status()),
+        Ok(Err(e)) => {
+            log::debug!("\"Probe of {} failed: {}\"", url, e);
+            None
+        }
+        Err(_) => {
+            log::debug!("\"Probe of {} timed out\"", url);
+            None
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_elasticsearch, ResourceLookup};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type IlmLookup = ResourceLookup<deployment::IlmExplain>;
+
+pub struct Ilm;
+
+#[async_trait]
+impl Collector for Ilm {
+    type Payload = Vec<IlmLookup>;
+
+    fn name(&self) -> &str {
+        "ilm"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Indices of each Elasticsearch resource stuck on an ILM error. Hidden
+    // indices, such as data stream backing indices, only match patterns
+    // starting with a dot, hence both patterns.
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_elasticsearch(state, collection, |state, deployment_id, ref_id| async move {
+            let path = format!(
+                "{}/{}/elasticsearch/{}/proxy/*,.*/_ilm/explain?only_errors=true&filter_path=indices.*.policy",
+                DEPLOYMENTS_PATH, deployment_id, ref_id
+            );
+            state.get_json(&path).await
+        })
+        .await
+    }
+
+    // Exported as zero when nothing is stuck, so it can be alerted on
+    async fn emit(&self, _state: &State, lookups: Self::Payload) -> Result<(), RestError> {
+        emit_lookups(lookups, "ILM errors", |resource, explain| {
+            metrics::gauge!(
+                "ece_deployment_ilm_error_indices_total",
+                explain.indices.len() as f64,
+                &resource.labels()
+            );
+        })
+    }
+}
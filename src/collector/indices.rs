@@ -0,0 +1,65 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_elasticsearch, ResourceLookup};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type IndicesLookup = ResourceLookup<deployment::ResolvedIndices>;
+
+pub struct Indices;
+
+#[async_trait]
+impl Collector for Indices {
+    type Payload = Vec<IndicesLookup>;
+
+    fn name(&self) -> &str {
+        "indices"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Every index and data stream of each Elasticsearch resource, hidden and
+    // closed ones included, as runaway index creation often goes through them
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_elasticsearch(
+            state,
+            collection,
+            |state, deployment_id, ref_id| async move {
+                let path = format!(
+                    "{}/{}/elasticsearch/{}/proxy/_resolve/index/*?expand_wildcards=all",
+                    DEPLOYMENTS_PATH, deployment_id, ref_id
+                );
+                state.get_json(&path).await
+            },
+        )
+        .await
+    }
+
+    async fn emit(&self, _state: &State, lookups: Self::Payload) -> Result<(), RestError> {
+        emit_lookups(lookups, "indices", |resource, resolved| {
+            let labels = resource.labels();
+            metrics::gauge!(
+                "ece_deployment_indices_total",
+                resolved.indices.len() as f64,
+                &labels
+            );
+            metrics::gauge!(
+                "ece_deployment_data_streams_total",
+                resolved.data_streams.len() as f64,
+                &labels
+            );
+        })
+    }
+}
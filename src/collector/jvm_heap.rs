@@ -0,0 +1,70 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_instance, PlacedLookups};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type JvmHeap = PlacedLookups<deployment::NodesStats>;
+
+pub struct Heap;
+
+#[async_trait]
+impl Collector for Heap {
+    type Payload = JvmHeap;
+
+    fn name(&self) -> &str {
+        "jvm_heap"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // JVM heap of each node of each Elasticsearch resource, trimmed to the
+    // fields exported as node stats are large
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_instance(state, collection, |state, deployment_id, ref_id| async move {
+            let path = format!(
+                "{}/{}/elasticsearch/{}/proxy/_nodes/stats/jvm?filter_path=nodes.*.name,nodes.*.jvm.mem.heap_used_in_bytes,nodes.*.jvm.mem.heap_max_in_bytes",
+                DEPLOYMENTS_PATH, deployment_id, ref_id
+            );
+            state.get_json(&path).await
+        })
+        .await
+    }
+
+    async fn emit(&self, _state: &State, heap: Self::Payload) -> Result<(), RestError> {
+        let PlacedLookups {
+            lookups,
+            placements,
+        } = heap;
+        emit_lookups(lookups, "node stats", |resource, stats| {
+            for node in stats.nodes.values() {
+                let Some(mem) = node.jvm.as_ref().map(|jvm| &jvm.mem) else {
+                    continue;
+                };
+                let labels = placements.labels(resource, &node.name);
+                metrics::gauge!(
+                    "ece_instance_jvm_heap_used_bytes",
+                    mem.heap_used_in_bytes as f64,
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_instance_jvm_heap_max_bytes",
+                    mem.heap_max_in_bytes as f64,
+                    &labels
+                );
+            }
+        })
+    }
+}
@@ -0,0 +1,80 @@
+use async_trait::async_trait;
+
+use crate::collector::elasticsearch_clusters::emit_version_drift;
+use crate::collector::{Collection, Collector};
+use crate::error::Error as RestError;
+use crate::kibana;
+use crate::state::{State, KIBANA_PATH};
+
+pub struct KibanaClusters;
+
+#[async_trait]
+impl Collector for KibanaClusters {
+    type Payload = kibana::KibanaClustersRoot;
+
+    fn name(&self) -> &str {
+        "kibana"
+    }
+
+    fn path(&self) -> &str {
+        KIBANA_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    async fn fetch(
+        &self,
+        state: &State,
+        _collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        state.get_json(KIBANA_PATH).await
+    }
+
+    async fn emit(&self, state: &State, body: Self::Payload) -> Result<(), RestError> {
+        log::debug!("{:#?}", body);
+        let names = state.names().await;
+
+        for cluster in &body.kibana_clusters {
+            log::debug!("\"Working on kibana cluster: {}\"", cluster.cluster_id);
+            let version = cluster
+                .plan()
+                .and_then(|plan| plan["kibana"]["version"].as_str())
+                .unwrap_or("null");
+            let labels = [
+                ("cluster_id", cluster.cluster_id.clone()),
+                ("name", cluster.cluster_name.clone()),
+                (
+                    "deployment_id",
+                    cluster.deployment_id.clone().unwrap_or("null".to_string()),
+                ),
+                (
+                    "deployment_name",
+                    names
+                        .deployment_of(cluster.deployment_id.as_deref(), &cluster.cluster_id)
+                        .to_string(),
+                ),
+                (
+                    "elasticsearch_id",
+                    match &cluster.elasticsearch_cluster {
+                        Some(es) => es.elasticsearch_id.clone(),
+                        None => "null".to_string(),
+                    },
+                ),
+                ("status", cluster.status.clone()),
+                ("version", version.to_string()),
+            ];
+            metrics::gauge!("ece_kibana_cluster_info", 1f64, &labels);
+
+            let labels = [
+                ("cluster_id", cluster.cluster_id.clone()),
+                ("name", cluster.cluster_name.clone()),
+            ];
+            let healthy = if cluster.healthy { 1f64 } else { 0f64 };
+            metrics::gauge!("ece_kibana_cluster_healthy", healthy, &labels);
+            emit_version_drift(&cluster.cluster_id, cluster.topology.as_ref(), version);
+        }
+        Ok(())
+    }
+}
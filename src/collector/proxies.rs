@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+use crate::collector::{Collection, Collector};
+use crate::error::Error as RestError;
+use crate::proxy;
+use crate::state::{State, PROXIES_PATH};
+
+pub struct Proxies;
+
+#[async_trait]
+impl Collector for Proxies {
+    type Payload = proxy::ProxiesRoot;
+
+    fn name(&self) -> &str {
+        "proxies"
+    }
+
+    fn path(&self) -> &str {
+        PROXIES_PATH
+    }
+
+    async fn fetch(
+        &self,
+        state: &State,
+        _collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        state.get_json(PROXIES_PATH).await
+    }
+
+    async fn emit(&self, state: &State, body: Self::Payload) -> Result<(), RestError> {
+        log::debug!("{:#?}", body);
+
+        let transitions = state.health_events.lock().unwrap().observe_proxies(&body);
+        state.record_transitions(transitions);
+
+        {
+            let mut diagnostics = state.diagnostics.lock().unwrap();
+            diagnostics.proxies = body.proxies.len();
+            diagnostics.proxies_unhealthy = body.proxies.iter().filter(|p| !p.healthy).count();
+        }
+
+        let unhealthy = body.proxies.iter().filter(|p| !p.healthy).count();
+        metrics::gauge!("ece_proxies_unhealthy_total", unhealthy as f64);
+
+        // ECE's own count should match the proxies it lists
+        metrics::gauge!("ece_proxies_reported_total", body.proxies_count as f64);
+        metrics::gauge!(
+            "ece_proxies_count_mismatch",
+            if body.proxies_count == body.proxies.len() as u64 {
+                0f64
+            } else {
+                1f64
+            }
+        );
+
+        // Losing a zone's proxies matters far more than a single proxy flapping
+        let mut zones: HashMap<&str, (u64, u64)> = HashMap::new();
+        for proxy in &body.proxies {
+            let zone = zones.entry(&proxy.zone).or_default();
+            zone.0 += 1;
+            if proxy.healthy {
+                zone.1 += 1;
+            }
+        }
+        for (zone, (total, healthy)) in zones {
+            metrics::gauge!("ece_proxies_total", total as f64, "zone" => zone.to_string());
+            metrics::gauge!("ece_proxies_healthy", healthy as f64, "zone" => zone.to_string());
+        }
+
+        for proxy in body.proxies {
+            log::debug!("\"Working on proxy: {}\"", proxy.proxy_id);
+            let labels = [
+                ("zone", proxy.zone.clone()),
+                ("hostname", proxy.public_hostname.to_owned()),
+                ("proxy_id", proxy.proxy_id.to_owned()),
+                (
+                    "proxy_ip",
+                    proxy.proxy_ip.unwrap_or("null".to_string()).to_owned(),
+                ),
+                ("healthy", proxy.healthy.to_string()),
+            ];
+            metrics::gauge!("ece_proxy_info", 1f64, &labels);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{collect, fixture, gauge, state, MockApi};
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn parse_proxies_emits_health() {
+        let api =
+            Arc::new(MockApi::new().respond(PROXIES_PATH, StatusCode::OK, fixture!("proxies")));
+        let state = state(api, &["--collectors", "proxies"]).await;
+        collect(&state, "proxies").await.unwrap();
+
+        assert_eq!(gauge("ece_proxies_unhealthy_total", &[]), Some(1.0));
+        assert_eq!(
+            gauge(
+                "ece_proxy_info",
+                &[
+                    ("proxy_id", "p2"),
+                    ("proxy_ip", "null"),
+                    ("healthy", "false")
+                ]
+            ),
+            Some(1.0)
+        );
+        // The fixture reports three proxies but lists two
+        assert_eq!(gauge("ece_proxies_reported_total", &[]), Some(3.0));
+        assert_eq!(gauge("ece_proxies_count_mismatch", &[]), Some(1.0));
+        let zone2 = [("zone", "zone-2")];
+        assert_eq!(gauge("ece_proxies_total", &zone2), Some(1.0));
+        assert_eq!(gauge("ece_proxies_healthy", &zone2), Some(0.0));
+        assert_eq!(state.diagnostics.lock().unwrap().proxies, 2);
+    }
+}
@@ -0,0 +1,103 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_elasticsearch, ResourceLookup};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type RemoteLookup = ResourceLookup<deployment::RemoteResources>;
+
+pub struct RemoteClusters;
+
+#[async_trait]
+impl Collector for RemoteClusters {
+    type Payload = Vec<RemoteLookup>;
+
+    fn name(&self) -> &str {
+        "remote_clusters"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Remote clusters (CCS/CCR) configured on each Elasticsearch resource
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_elasticsearch(
+            state,
+            collection,
+            |state, deployment_id, ref_id| async move {
+                let path = format!(
+                    "{}/{}/elasticsearch/{}/remote-clusters",
+                    DEPLOYMENTS_PATH, deployment_id, ref_id
+                );
+                state.get_json(&path).await
+            },
+        )
+        .await
+    }
+
+    async fn emit(&self, _state: &State, lookups: Self::Payload) -> Result<(), RestError> {
+        emit_lookups(lookups, "remote clusters", |resource, remotes| {
+            let labels = resource.labels();
+            metrics::gauge!(
+                "ece_deployment_remote_clusters",
+                remotes.resources.len() as f64,
+                &labels
+            );
+
+            for remote in &remotes.resources {
+                let labels = [
+                    ("deployment_id", resource.deployment_id.clone()),
+                    ("ref_id", resource.ref_id.clone()),
+                    ("alias", remote.alias.clone()),
+                    ("remote_deployment_id", remote.deployment_id.clone()),
+                    ("remote_ref_id", remote.elasticsearch_ref_id.clone()),
+                    ("skip_unavailable", remote.skip_unavailable.to_string()),
+                    (
+                        "version",
+                        remote
+                            .info
+                            .as_ref()
+                            .and_then(|info| info.version.clone())
+                            .unwrap_or("null".to_string()),
+                    ),
+                ];
+                metrics::gauge!("ece_deployment_remote_cluster_info", 1f64, &labels);
+
+                // Without info ECE couldn't check the link, so report it as down
+                let info = remote.info.as_ref();
+                let labels = [
+                    ("deployment_id", resource.deployment_id.clone()),
+                    ("ref_id", resource.ref_id.clone()),
+                    ("alias", remote.alias.clone()),
+                ];
+                let gauge = |flag: Option<bool>| if flag.unwrap_or(false) { 1f64 } else { 0f64 };
+                metrics::gauge!(
+                    "ece_deployment_remote_cluster_connected",
+                    gauge(info.map(|i| i.connected)),
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_deployment_remote_cluster_healthy",
+                    gauge(info.map(|i| i.healthy)),
+                    &labels
+                );
+                metrics::gauge!(
+                    "ece_deployment_remote_cluster_compatible",
+                    gauge(info.map(|i| i.compatible)),
+                    &labels
+                );
+            }
+        })
+    }
+}
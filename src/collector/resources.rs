@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::allocator;
+use crate::collector::Collection;
+use crate::config::AllocatorLabel;
+use crate::error::Error as RestError;
+use crate::state::State;
+
+// An Elasticsearch resource of one of the searched deployments
+pub struct Resource {
+    pub deployment_id: String,
+    pub deployment_name: String,
+    pub ref_id: String,
+    pub cluster_id: String,
+}
+
+impl Resource {
+    pub fn labels(&self) -> [(&'static str, String); 3] {
+        [
+            ("deployment_id", self.deployment_id.clone()),
+            ("deployment_name", self.deployment_name.clone()),
+            ("ref_id", self.ref_id.clone()),
+        ]
+    }
+}
+
+// What was fetched for one Elasticsearch resource, or why it couldn't be
+pub struct ResourceLookup<T> {
+    resource: Resource,
+    result: Result<T, RestError>,
+}
+
+// Export the lookups that succeeded and log the ones that failed. Any failure
+// fails the collector once the others are exported, with the first one in
+// deployments search order, as later ones usually share its cause.
+pub fn emit_lookups<T>(
+    lookups: Vec<ResourceLookup<T>>,
+    what: &str,
+    mut emit: impl FnMut(&Resource, T),
+) -> Result<(), RestError> {
+    let mut failure = None;
+    for lookup in lookups {
+        match lookup.result {
+            Ok(value) => emit(&lookup.resource, value),
+            Err(e) => {
+                log::error!(
+                    "{{\"msg\": \"unable to fetch {}\", \"deployment_id\": \"{}\", \"error\": {}}}",
+                    what,
+                    lookup.resource.deployment_id,
+                    e
+                );
+                failure.get_or_insert(e);
+            }
+        }
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+// Zone and allocator of each instance, keyed by cluster and instance name
+#[derive(Default)]
+pub struct Placements(HashMap<(String, String), (String, String)>);
+
+impl Placements {
+    pub fn new(body: &allocator::AllocatorsRoot, label: AllocatorLabel) -> Self {
+        let mut placements = HashMap::new();
+        for zone in &body.zones {
+            for allocator in &zone.allocators {
+                for instance in &allocator.instances {
+                    placements.insert(
+                        (instance.cluster_id.clone(), instance.instance_name.clone()),
+                        (zone.zone_id.clone(), label.value(allocator).to_string()),
+                    );
+                }
+            }
+        }
+        Placements(placements)
+    }
+
+    // Labels of an instance, placed on its allocator when known
+    pub fn labels(&self, resource: &Resource, instance_name: &str) -> [(&'static str, String); 6] {
+        let placement = self
+            .0
+            .get(&(resource.cluster_id.clone(), instance_name.to_string()));
+        let (zone, ip) = match placement {
+            Some((zone, ip)) => (zone.clone(), ip.clone()),
+            None => ("null".to_string(), "null".to_string()),
+        };
+        let [deployment_id, deployment_name, ref_id] = resource.labels();
+        [
+            deployment_id,
+            deployment_name,
+            ref_id,
+            ("instance_name", instance_name.to_string()),
+            ("zone", zone),
+            ("ip", ip),
+        ]
+    }
+}
+
+// Per-instance lookups of each Elasticsearch resource, and where the
+// instances run
+pub struct PlacedLookups<T> {
+    pub lookups: Vec<ResourceLookup<T>>,
+    pub placements: Arc<Placements>,
+}
+
+// Run a lookup for each Elasticsearch resource of the searched deployments,
+// concurrently with one request per resource
+pub async fn per_elasticsearch<T, F, Fut>(
+    state: &State,
+    collection: &Collection,
+    lookup: F,
+) -> Result<Vec<ResourceLookup<T>>, RestError>
+where
+    T: Send + 'static,
+    F: Fn(State, String, String) -> Fut + Clone + Send + 'static,
+    Fut: std::future::Future<Output = Result<T, RestError>> + Send + 'static,
+{
+    let body = collection.deployments(state).await?;
+
+    let mut tasks = Vec::new();
+    for deployment in &body.deployments {
+        for resource in &deployment.resources.elasticsearch {
+            let state = state.clone();
+            let lookup = lookup.clone();
+            let deployment_id = deployment.id.clone();
+            let deployment_name = deployment.name.clone();
+            let ref_id = resource.ref_id.clone();
+            let cluster_id = resource.id.clone();
+            tasks.push(tokio::spawn(async move {
+                let result = lookup(state, deployment_id.clone(), ref_id.clone()).await;
+                ResourceLookup {
+                    resource: Resource {
+                        deployment_id,
+                        deployment_name,
+                        ref_id,
+                        cluster_id,
+                    },
+                    result,
+                }
+            }));
+        }
+    }
+
+    let mut lookups = Vec::new();
+    for task in tasks {
+        lookups.push(task.await.expect("resource lookup panicked"));
+    }
+    Ok(lookups)
+}
+
+// Run a per-instance lookup for each Elasticsearch resource, placing the
+// instances on their allocators
+pub async fn per_instance<T, F, Fut>(
+    state: &State,
+    collection: &Collection,
+    lookup: F,
+) -> Result<PlacedLookups<T>, RestError>
+where
+    T: Send + 'static,
+    F: Fn(State, String, String) -> Fut + Clone + Send + 'static,
+    Fut: std::future::Future<Output = Result<T, RestError>> + Send + 'static,
+{
+    let placements = collection.placements(state).await;
+    let lookups = per_elasticsearch(state, collection, lookup).await?;
+    Ok(PlacedLookups {
+        lookups,
+        placements,
+    })
+}
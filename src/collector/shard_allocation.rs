@@ -0,0 +1,71 @@
+use async_trait::async_trait;
+
+use crate::collector::resources::{emit_lookups, per_instance, PlacedLookups};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type ShardAllocation = PlacedLookups<Vec<deployment::CatAllocation>>;
+
+pub struct Shards;
+
+#[async_trait]
+impl Collector for Shards {
+    type Payload = ShardAllocation;
+
+    fn name(&self) -> &str {
+        "shard_allocation"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Shards and disk per instance of each Elasticsearch resource
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_instance(
+            state,
+            collection,
+            |state, deployment_id, ref_id| async move {
+                let path = format!(
+                    "{}/{}/elasticsearch/{}/proxy/_cat/allocation?format=json&bytes=b",
+                    DEPLOYMENTS_PATH, deployment_id, ref_id
+                );
+                state.get_json(&path).await
+            },
+        )
+        .await
+    }
+
+    async fn emit(&self, _state: &State, allocation: Self::Payload) -> Result<(), RestError> {
+        let PlacedLookups {
+            lookups,
+            placements,
+        } = allocation;
+        emit_lookups(lookups, "shard allocation", |resource, rows| {
+            // Unassigned shards have a row of their own, without a node
+            for row in rows.iter().filter(|row| row.node != "UNASSIGNED") {
+                let labels = placements.labels(resource, &row.node);
+                if let Some(shards) = row.shards() {
+                    metrics::gauge!("ece_deployment_instance_shards", shards, &labels);
+                }
+                if let Some(disk_used) = row.disk_used() {
+                    metrics::gauge!(
+                        "ece_deployment_instance_disk_used_bytes",
+                        disk_used,
+                        &labels
+                    );
+                }
+            }
+        })
+    }
+}
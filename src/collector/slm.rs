@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+
+use crate::collector::resources::{emit_lookups, per_elasticsearch, ResourceLookup};
+use crate::collector::{Collection, Collector};
+use crate::deployment;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENTS_PATH, DEPLOYMENTS_SEARCH_PATH};
+
+pub type SlmLookup = ResourceLookup<HashMap<String, deployment::SlmPolicy>>;
+
+pub struct Slm;
+
+#[async_trait]
+impl Collector for Slm {
+    type Payload = Vec<SlmLookup>;
+
+    fn name(&self) -> &str {
+        "slm"
+    }
+
+    fn path(&self) -> &str {
+        DEPLOYMENTS_SEARCH_PATH
+    }
+
+    fn per_cluster(&self) -> bool {
+        true
+    }
+
+    // Snapshot lifecycle policies of each Elasticsearch resource
+    async fn fetch(
+        &self,
+        state: &State,
+        collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        per_elasticsearch(
+            state,
+            collection,
+            |state, deployment_id, ref_id| async move {
+                let path = format!(
+                    "{}/{}/elasticsearch/{}/proxy/_slm/policy",
+                    DEPLOYMENTS_PATH, deployment_id, ref_id
+                );
+                state.get_json(&path).await
+            },
+        )
+        .await
+    }
+
+    // When each policy last succeeded and failed, for alerting on snapshots
+    // going stale. Policies that never ran have neither.
+    async fn emit(&self, _state: &State, lookups: Self::Payload) -> Result<(), RestError> {
+        emit_lookups(lookups, "snapshot policies", |resource, policies| {
+            for (policy, status) in &policies {
+                let mut labels = resource.labels().to_vec();
+                labels.push(("policy", policy.clone()));
+                if let Some(success) = &status.last_success {
+                    metrics::gauge!(
+                        "ece_deployment_slm_last_success_timestamp_seconds",
+                        success.time as f64 / 1000.0,
+                        &labels
+                    );
+                }
+                if let Some(failure) = &status.last_failure {
+                    metrics::gauge!(
+                        "ece_deployment_slm_last_failure_timestamp_seconds",
+                        failure.time as f64 / 1000.0,
+                        &labels
+                    );
+                }
+            }
+        })
+    }
+}
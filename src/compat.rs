@@ -0,0 +1,108 @@
+use metrics::{Counter, Gauge, Histogram, Key, KeyName, Label, Recorder, Unit};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+// Schemes shipped with the exporter, which --compat takes by name
+const BUILTIN: &[(&str, &str)] = &[("ecs", include_str!("compat/ecs.yaml"))];
+
+// Metric and label renames loaded from a --compat scheme
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Scheme {
+    #[serde(default)]
+    pub metrics: HashMap<String, String>,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+}
+
+impl Scheme {
+    // A built-in scheme by name, or else a scheme file by path
+    pub fn load(source: &str) -> Result<Self, String> {
+        let contents = match BUILTIN.iter().find(|(name, _)| *name == source) {
+            Some((_, contents)) => contents.to_string(),
+            None => std::fs::read_to_string(source)
+                .map_err(|e| format!("compat scheme {} is not readable: {}", source, e))?,
+        };
+        serde_yaml::from_str(&contents)
+            .map_err(|e| format!("compat scheme {} is not valid: {}", source, e))
+    }
+
+    pub fn metric(&self, name: &str) -> String {
+        self.metrics
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    pub fn label(&self, name: &str) -> String {
+        self.labels
+            .get(name)
+            .cloned()
+            .unwrap_or_else(|| name.to_string())
+    }
+
+    fn key(&self, key: &Key) -> Key {
+        let labels: Vec<Label> = key
+            .labels()
+            .map(|label| Label::new(self.label(label.key()), label.value().to_string()))
+            .collect();
+        Key::from_parts(self.metric(key.name()), labels)
+    }
+}
+
+// Recorder that applies a scheme to every series before handing it on
+pub struct CompatRecorder<R> {
+    inner: R,
+    scheme: Scheme,
+}
+
+impl<R> CompatRecorder<R> {
+    pub fn new(inner: R, scheme: Scheme) -> Self {
+        CompatRecorder { inner, scheme }
+    }
+}
+
+impl<R: Recorder> Recorder for CompatRecorder<R> {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        let key = KeyName::from(self.scheme.metric(key.as_str()));
+        self.inner.describe_counter(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        let key = KeyName::from(self.scheme.metric(key.as_str()));
+        self.inner.describe_gauge(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        let key = KeyName::from(self.scheme.metric(key.as_str()));
+        self.inner.describe_histogram(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        self.inner.register_counter(&self.scheme.key(key))
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        self.inner.register_gauge(&self.scheme.key(key))
+    }
+
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        self.inner.register_histogram(&self.scheme.key(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_schemes_load_by_name() {
+        for (name, _) in BUILTIN {
+            assert!(!Scheme::load(name).unwrap().labels.is_empty());
+        }
+        assert_eq!(
+            Scheme::load("ecs").unwrap().label("zone"),
+            "cloud_availability_zone"
+        );
+        assert!(Scheme::load("no-such-scheme").is_err());
+    }
+}
@@ -0,0 +1,857 @@
+use clap::{ArgMatches, ValueSource};
+use hyper::header::{HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use url::Url;
+
+use crate::allocator::Allocator;
+use crate::collector::Registry;
+use crate::compat::Scheme;
+use crate::custom::CustomCollector;
+use crate::state::DEPLOYMENT_COLLECTORS;
+use crate::template::{is_label_name, LabelTemplate};
+use crate::vault::VaultConfig;
+
+// Upper bound for any timeout, so a typo can't leave a scrape hanging for hours
+const MAX_TIMEOUT: u64 = 3600;
+
+// Beyond this many decimal places rounding makes no visible difference
+const MAX_COST_PRECISION: u32 = 6;
+
+// How the exporter authenticates against ECE
+pub enum Auth {
+    ApiKey(String),
+    ApiKeyFile(String),
+    #[cfg(feature = "aws")]
+    ApiKeyFrom(String),
+    Vault(VaultConfig),
+    Basic {
+        username: String,
+        password: Password,
+    },
+}
+
+pub enum Password {
+    Value(String),
+    File(String),
+}
+
+// Which allocator field identifies an allocator in the ip label
+#[derive(Clone, Copy, Debug)]
+pub enum AllocatorLabel {
+    Hostname,
+    HostIp,
+    AllocatorId,
+}
+
+impl AllocatorLabel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AllocatorLabel::Hostname => "hostname",
+            AllocatorLabel::HostIp => "host-ip",
+            AllocatorLabel::AllocatorId => "allocator-id",
+        }
+    }
+
+    // The allocator field this identity selects
+    pub fn value<'a>(&self, allocator: &'a Allocator) -> &'a str {
+        match self {
+            AllocatorLabel::Hostname => &allocator.public_hostname,
+            AllocatorLabel::HostIp => &allocator.host_ip,
+            AllocatorLabel::AllocatorId => &allocator.allocator_id,
+        }
+    }
+}
+
+// How cost gauges are reported: the unit, and optionally how many decimal
+// places they are rounded to
+#[derive(Clone, Copy, Debug)]
+pub struct CostFormat {
+    pub unit: CostUnit,
+    pub precision: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CostUnit {
+    Cents,
+    Dollars,
+}
+
+impl CostUnit {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CostUnit::Cents => "cents",
+            CostUnit::Dollars => "dollars",
+        }
+    }
+}
+
+impl CostFormat {
+    // Convert a cost in cents to the configured unit and precision
+    #[cfg(feature = "cost")]
+    pub fn apply(&self, cents: f64) -> f64 {
+        let value = match self.unit {
+            CostUnit::Cents => cents,
+            CostUnit::Dollars => cents / 100.0,
+        };
+        match self.precision {
+            Some(precision) => {
+                let factor = 10f64.powi(precision as i32);
+                (value * factor).round() / factor
+            }
+            None => value,
+        }
+    }
+}
+
+// Validated configuration, built once from the command line and environment
+pub struct Settings {
+    pub url: String,
+    pub port: u16,
+    pub auth: Auth,
+    pub connect_timeout: u64,
+    pub request_timeout: u64,
+    pub server_timeout: u64,
+    pub probe_timeout: u64,
+    pub eru_cost: u64,
+    pub cost_format: CostFormat,
+    pub eru_size_gb: u64,
+    pub eru_size_overrides: HashMap<String, u64>,
+    pub max_response_bytes: u64,
+    pub max_concurrent_requests: usize,
+    pub max_concurrent_scrapes: usize,
+    pub auth_failure_cooldown: Duration,
+    pub name_cache_ttl: Duration,
+    pub stale_while_revalidate: Duration,
+    pub collectors: Vec<String>,
+    pub custom_collectors: Vec<CustomCollector>,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub user_agent: HeaderValue,
+    pub mb_metrics: bool,
+    pub allocator_label: AllocatorLabel,
+    pub aggregate_only: bool,
+    pub listen_backlog: i32,
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub compat: Option<Scheme>,
+    pub deployment_query: Value,
+    pub tag_metrics: HashMap<String, String>,
+    pub instance_labels: Vec<LabelTemplate>,
+    pub drop_labels: Vec<String>,
+    pub snapshot_file: Option<String>,
+    pub debug_token: Option<String>,
+    pub textfile_output: Option<String>,
+    #[cfg(feature = "push")]
+    pub webhook_url: Option<String>,
+    #[cfg(feature = "push")]
+    pub license_expiry_warning: Duration,
+    pub refresh_interval: Duration,
+    pub refresh_jitter: Duration,
+    pub once: bool,
+    pub session_auth: bool,
+}
+
+impl Settings {
+    // Parse and validate every option, reporting all problems at once
+    pub fn from_opts(opts: &ArgMatches) -> Result<Self, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let url = match parse_url(opts.value_of("url").unwrap_or_default()) {
+            Ok(url) => url,
+            Err(e) => {
+                errors.push(e);
+                String::new()
+            }
+        };
+
+        let port = match opts.value_of("port").unwrap_or_default().parse::<u16>() {
+            Ok(0) => {
+                errors.push("port must be between 1 and 65535".to_string());
+                0
+            }
+            Ok(port) => port,
+            Err(e) => {
+                errors.push(format!("port is not valid: {}", e));
+                0
+            }
+        };
+
+        let timeout = parse_timeout(opts, "timeout", "timeout", &mut errors).unwrap_or(60);
+        let connect_timeout =
+            parse_timeout(opts, "connect_timeout", "connect-timeout", &mut errors)
+                .unwrap_or(timeout);
+        let request_timeout =
+            parse_timeout(opts, "request_timeout", "request-timeout", &mut errors)
+                .unwrap_or(timeout);
+        let server_timeout =
+            parse_timeout(opts, "server_timeout", "server-timeout", &mut errors).unwrap_or(timeout);
+        let probe_timeout =
+            parse_timeout(opts, "probe_timeout", "probe-timeout", &mut errors).unwrap_or(5);
+
+        let eru_cost = parse_positive(opts, "eru_cost", "eru_cost", &mut errors).unwrap_or(6000);
+        let eru_size_gb =
+            parse_positive(opts, "eru_size_gb", "eru-size-gb", &mut errors).unwrap_or(64);
+        let eru_size_overrides = eru_size_overrides(opts).unwrap_or_else(|e| {
+            errors.extend(e);
+            HashMap::new()
+        });
+        let cost_unit = match opts.value_of("cost_unit").unwrap_or_default() {
+            "cents" => CostUnit::Cents,
+            "dollars" => CostUnit::Dollars,
+            other => {
+                errors.push(format!(
+                    "cost-unit {} must be one of: cents, dollars",
+                    other
+                ));
+                CostUnit::Cents
+            }
+        };
+        let cost_precision = parse::<u32>(opts, "cost_precision", "cost-precision", &mut errors);
+        if let Some(precision) = cost_precision.filter(|p| *p > MAX_COST_PRECISION) {
+            errors.push(format!(
+                "cost-precision {} must be at most {}",
+                precision, MAX_COST_PRECISION
+            ));
+        }
+        let max_response_bytes = parse_positive(
+            opts,
+            "max_response_bytes",
+            "max-response-bytes",
+            &mut errors,
+        )
+        .unwrap_or(104857600);
+        let max_concurrent_requests = parse_positive(
+            opts,
+            "max_concurrent_requests",
+            "max-concurrent-requests",
+            &mut errors,
+        )
+        .unwrap_or(8);
+        let max_concurrent_scrapes = parse_positive(
+            opts,
+            "max_concurrent_scrapes",
+            "max-concurrent-scrapes",
+            &mut errors,
+        )
+        .unwrap_or(4);
+        let refresh_interval =
+            parse_positive(opts, "refresh_interval", "refresh-interval", &mut errors).unwrap_or(60);
+        let auth_failure_cooldown = parse(
+            opts,
+            "auth_failure_cooldown",
+            "auth-failure-cooldown",
+            &mut errors,
+        )
+        .unwrap_or(300);
+        let name_cache_ttl =
+            parse(opts, "name_cache_ttl", "name-cache-ttl", &mut errors).unwrap_or(300);
+        let stale_while_revalidate = parse(
+            opts,
+            "stale_while_revalidate",
+            "stale-while-revalidate",
+            &mut errors,
+        )
+        .unwrap_or(0);
+        let refresh_jitter =
+            parse(opts, "refresh_jitter", "refresh-jitter", &mut errors).unwrap_or(0);
+
+        let auth = match parse_auth(opts) {
+            Ok(auth) => Some(auth),
+            Err(e) => {
+                errors.extend(e);
+                None
+            }
+        };
+
+        let headers = headers(opts).unwrap_or_else(|e| {
+            errors.push(e);
+            Vec::new()
+        });
+        let user_agent = HeaderValue::from_str(opts.value_of("user_agent").unwrap_or_default())
+            .unwrap_or_else(|e| {
+                errors.push(format!("user-agent is not valid: {}", e));
+                HeaderValue::from_static("")
+            });
+
+        let registry = Registry::builtin();
+        let collectors = collectors(opts);
+        if collectors.is_empty() {
+            errors.push("at least one collector must be enabled".to_string());
+        }
+        for collector in &collectors {
+            if registry.get(collector).is_some() {
+                continue;
+            }
+            match DEPLOYMENT_COLLECTORS.contains(&collector.as_str()) {
+                true => errors.push(format!(
+                    "collector {} needs the deployments feature, which this build was compiled without",
+                    collector
+                )),
+                false => errors.push(format!(
+                    "unknown collector {}, expected one of: {}",
+                    collector,
+                    registry.names().join(", ")
+                )),
+            }
+        }
+        require_feature(
+            opts,
+            cfg!(feature = "aws"),
+            "aws",
+            &[("apikey_from", "apikey-from")],
+            &mut errors,
+        );
+        require_feature(
+            opts,
+            cfg!(feature = "deployments"),
+            "deployments",
+            &[
+                ("deployment_query", "deployment-query"),
+                ("probe_timeout", "probe-timeout"),
+            ],
+            &mut errors,
+        );
+        require_feature(
+            opts,
+            cfg!(feature = "cost"),
+            "cost",
+            &[
+                ("eru_cost", "eru_cost"),
+                ("eru_size_gb", "eru-size-gb"),
+                ("eru_size_gb_override", "eru-size-gb-override"),
+                ("cost_unit", "cost-unit"),
+                ("cost_precision", "cost-precision"),
+            ],
+            &mut errors,
+        );
+        require_feature(
+            opts,
+            cfg!(feature = "push"),
+            "push",
+            &[
+                ("webhook_url", "webhook-url"),
+                ("license_expiry_warning", "license-expiry-warning"),
+                ("textfile_output", "textfile-output"),
+            ],
+            &mut errors,
+        );
+
+        let listen_backlog =
+            parse_positive(opts, "listen_backlog", "listen-backlog", &mut errors).unwrap_or(1024);
+        if listen_backlog > i32::MAX as u64 {
+            errors.push(format!("listen-backlog must be at most {}", i32::MAX));
+        }
+        let tcp_keepalive = parse_positive(opts, "tcp_keepalive", "tcp-keepalive", &mut errors)
+            .map(Duration::from_secs);
+
+        // The cluster collectors export one series set per cluster by design
+        let aggregate_only = opts.is_present("aggregate_only");
+        if aggregate_only {
+            for collector in &collectors {
+                if registry.get(collector).is_some_and(|c| c.per_cluster()) {
+                    errors.push(format!(
+                        "aggregate-only cannot be combined with the {} collector",
+                        collector
+                    ));
+                }
+            }
+        }
+
+        // Custom collectors always run, on top of the --collectors ones
+        let custom_collectors = match opts.value_of("custom_metrics") {
+            Some(path) => CustomCollector::load(path, &registry.names()).unwrap_or_else(|e| {
+                errors.extend(e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+        let mut collectors = collectors;
+        collectors.extend(custom_collectors.iter().map(|c| c.name.clone()));
+
+        let compat = opts
+            .value_of("compat")
+            .and_then(|path| Scheme::load(path).map_err(|e| errors.push(e)).ok());
+
+        let deployment_query = match opts.value_of("deployment_query") {
+            Some(query) => match serde_json::from_str::<Value>(query) {
+                Ok(query) if query.is_object() => query,
+                Ok(_) => {
+                    errors.push("deployment-query must be a JSON object".to_string());
+                    Value::Null
+                }
+                Err(e) => {
+                    errors.push(format!("deployment-query is not valid JSON: {}", e));
+                    Value::Null
+                }
+            },
+            None => json!({ "query": { "match_all": {} } }),
+        };
+
+        let tag_metrics = tag_metrics(opts).unwrap_or_else(|e| {
+            errors.extend(e);
+            HashMap::new()
+        });
+
+        let instance_labels = instance_labels(opts).unwrap_or_else(|e| {
+            errors.extend(e);
+            Vec::new()
+        });
+
+        let mut drop_labels: Vec<String> = Vec::new();
+        for label in opts
+            .values_of("drop_label")
+            .unwrap_or_default()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+        {
+            if !is_label_name(label) {
+                errors.push(format!("drop-label {} is not a valid label name", label));
+            } else if !drop_labels.iter().any(|l| l == label) {
+                drop_labels.push(label.to_string());
+            }
+        }
+
+        #[cfg(feature = "push")]
+        let webhook_url = match opts.value_of("webhook_url").map(parse_webhook_url) {
+            Some(Ok(url)) => Some(url),
+            Some(Err(e)) => {
+                errors.push(e);
+                None
+            }
+            None => None,
+        };
+        #[cfg(feature = "push")]
+        let license_expiry_warning = parse::<u64>(
+            opts,
+            "license_expiry_warning",
+            "license-expiry-warning",
+            &mut errors,
+        )
+        .unwrap_or(30);
+
+        let textfile_output = opts.value_of("textfile_output").map(str::to_string);
+        if let Some(path) = &textfile_output {
+            let dir = Path::new(path)
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."));
+            if !dir.is_dir() {
+                errors.push(format!(
+                    "textfile-output directory {} does not exist",
+                    dir.display()
+                ));
+            }
+        }
+
+        let allocator_label = match opts.value_of("allocator_label").unwrap_or_default() {
+            "hostname" => AllocatorLabel::Hostname,
+            "host-ip" => AllocatorLabel::HostIp,
+            "allocator-id" => AllocatorLabel::AllocatorId,
+            other => {
+                errors.push(format!(
+                    "allocator-label {} must be one of: hostname, host-ip, allocator-id",
+                    other
+                ));
+                AllocatorLabel::Hostname
+            }
+        };
+
+        if opts.is_present("session_auth") && !matches!(auth, Some(Auth::Basic { .. }) | None) {
+            errors.push("session-auth requires username and password".to_string());
+        }
+
+        match (auth, errors.is_empty()) {
+            (Some(auth), true) => Ok(Settings {
+                url,
+                port,
+                auth,
+                connect_timeout,
+                request_timeout,
+                server_timeout,
+                probe_timeout,
+                eru_cost,
+                cost_format: CostFormat {
+                    unit: cost_unit,
+                    precision: cost_precision,
+                },
+                eru_size_gb,
+                eru_size_overrides,
+                max_response_bytes,
+                max_concurrent_requests: max_concurrent_requests as usize,
+                max_concurrent_scrapes: max_concurrent_scrapes as usize,
+                auth_failure_cooldown: Duration::from_secs(auth_failure_cooldown),
+                name_cache_ttl: Duration::from_secs(name_cache_ttl),
+                stale_while_revalidate: Duration::from_secs(stale_while_revalidate),
+                collectors,
+                custom_collectors,
+                headers,
+                user_agent,
+                mb_metrics: !opts.is_present("disable_mb_metrics"),
+                allocator_label,
+                aggregate_only,
+                listen_backlog: listen_backlog.min(i32::MAX as u64) as i32,
+                tcp_nodelay: opts.is_present("tcp_nodelay"),
+                tcp_keepalive,
+                compat,
+                deployment_query,
+                tag_metrics,
+                instance_labels,
+                drop_labels,
+                snapshot_file: opts.value_of("snapshot_file").map(str::to_string),
+                debug_token: opts.value_of("debug_token").map(str::to_string),
+                textfile_output,
+                #[cfg(feature = "push")]
+                webhook_url,
+                #[cfg(feature = "push")]
+                license_expiry_warning: Duration::from_secs(license_expiry_warning * 86400),
+                refresh_interval: Duration::from_secs(refresh_interval),
+                refresh_jitter: Duration::from_secs(refresh_jitter),
+                once: opts.is_present("once"),
+                session_auth: opts.is_present("session_auth"),
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+// Normalize the base url so API paths can be appended with a single slash,
+// keeping any sub-path ECE is served under, e.g. https://lb.example.com/ece
+fn parse_url(value: &str) -> Result<String, String> {
+    let url = Url::parse(value.trim()).map_err(|e| format!("url is not valid: {}", e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!(
+            "url scheme must be http or https, got {}",
+            url.scheme()
+        ));
+    }
+    if !url.has_host() {
+        return Err("url is missing a host".to_string());
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err("url must not contain a query string or fragment".to_string());
+    }
+    if !url.username().is_empty() || url.password().is_some() {
+        return Err("url must not contain credentials, use --username/--password".to_string());
+    }
+
+    let base = url.as_str().trim_end_matches('/').to_string();
+    let probe = format!("{}/api/v1/platform", base);
+    probe
+        .parse::<hyper::Uri>()
+        .map_err(|e| format!("url {} does not produce valid request uris: {}", base, e))?;
+    Ok(base)
+}
+
+// The webhook is called as given, so only check it is an http(s) url
+#[cfg(feature = "push")]
+fn parse_webhook_url(value: &str) -> Result<String, String> {
+    let url = Url::parse(value.trim()).map_err(|e| format!("webhook-url is not valid: {}", e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(format!(
+            "webhook-url scheme must be http or https, got {}",
+            url.scheme()
+        ));
+    }
+    url.as_str()
+        .parse::<hyper::Uri>()
+        .map_err(|e| format!("webhook-url is not valid: {}", e))?;
+    Ok(url.to_string())
+}
+
+// Reject options set for a cargo feature left out of this build, rather than
+// silently ignoring them. Defaults don't count as set.
+fn require_feature(
+    opts: &ArgMatches,
+    enabled: bool,
+    feature: &str,
+    options: &[(&str, &str)],
+    errors: &mut Vec<String>,
+) {
+    if enabled {
+        return;
+    }
+    for (id, name) in options {
+        if matches!(
+            opts.value_source(id),
+            Some(ValueSource::EnvVariable | ValueSource::CommandLine)
+        ) {
+            errors.push(format!(
+                "{} needs the {} feature, which this build was compiled without",
+                name, feature
+            ));
+        }
+    }
+}
+
+// Parse an optional numeric option, recording an error if it's malformed
+fn parse<T>(opts: &ArgMatches, id: &str, name: &str, errors: &mut Vec<String>) -> Option<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    match opts.value_of(id)?.parse::<T>() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            errors.push(format!("{} is not valid: {}", name, e));
+            None
+        }
+    }
+}
+
+fn parse_positive(
+    opts: &ArgMatches,
+    id: &str,
+    name: &str,
+    errors: &mut Vec<String>,
+) -> Option<u64> {
+    match parse::<u64>(opts, id, name, errors)? {
+        0 => {
+            errors.push(format!("{} must be greater than zero", name));
+            None
+        }
+        value => Some(value),
+    }
+}
+
+fn parse_timeout(opts: &ArgMatches, id: &str, name: &str, errors: &mut Vec<String>) -> Option<u64> {
+    let value = parse_positive(opts, id, name, errors)?;
+    if value > MAX_TIMEOUT {
+        errors.push(format!("{} must be at most {} seconds", name, MAX_TIMEOUT));
+        return None;
+    }
+    Some(value)
+}
+
+// Work out the single auth mode from the supplied credentials
+fn parse_auth(opts: &ArgMatches) -> Result<Auth, Vec<String>> {
+    let mut errors = Vec::new();
+
+    let has_apikey = opts.is_present("apikey")
+        || opts.is_present("apikey_file")
+        || opts.is_present("apikey_from")
+        || opts.is_present("vault_addr");
+    let has_username = opts.is_present("username");
+    let has_password = opts.is_present("password") || opts.is_present("password_file");
+    if has_apikey && (has_username || has_password) {
+        errors.push("apikey or vault cannot be combined with username/password".to_string());
+    }
+    if !(has_apikey || has_username && has_password) {
+        errors.push("either apikey, vault, or both username and password are required".to_string());
+    }
+
+    for file in ["apikey_file", "password_file"] {
+        if let Some(path) = opts.value_of(file) {
+            if let Err(e) = std::fs::metadata(path) {
+                errors.push(format!("{} {} is not readable: {}", file, path, e));
+            }
+        }
+    }
+
+    if let Some(source) = opts.value_of("apikey_from") {
+        if !source.starts_with("aws-sm://") && !source.starts_with("aws-ssm://") {
+            errors.push(format!(
+                "apikey-from {} must start with aws-sm:// or aws-ssm://",
+                source
+            ));
+        }
+    }
+
+    let vault = match opts.value_of("vault_addr") {
+        Some(addr) => {
+            if let Err(e) = Url::parse(addr) {
+                errors.push(format!("vault-addr is not valid: {}", e));
+            }
+            if !opts.is_present("vault_token") {
+                errors.push("vault-token is required when using vault".to_string());
+            }
+            let refresh_interval = parse_positive(
+                opts,
+                "vault_refresh_interval",
+                "vault-refresh-interval",
+                &mut errors,
+            );
+            Some(VaultConfig {
+                addr: addr.trim_end_matches('/').to_string(),
+                path: opts
+                    .value_of("vault_path")
+                    .unwrap_or_default()
+                    .trim_matches('/')
+                    .to_string(),
+                token: opts.value_of("vault_token").unwrap_or_default().to_string(),
+                key: opts.value_of("vault_key").unwrap_or_default().to_string(),
+                refresh_interval: Duration::from_secs(refresh_interval.unwrap_or(300)),
+            })
+        }
+        None => None,
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let value = |id: &str| opts.value_of(id).map(str::to_string);
+    #[cfg(feature = "aws")]
+    let from_aws = value("apikey_from").map(Auth::ApiKeyFrom);
+    #[cfg(not(feature = "aws"))]
+    let from_aws = None;
+    let auth = if let Some(vault) = vault {
+        Auth::Vault(vault)
+    } else if let Some(auth) = from_aws {
+        auth
+    } else if let Some(path) = value("apikey_file") {
+        Auth::ApiKeyFile(path)
+    } else if let Some(key) = value("apikey") {
+        Auth::ApiKey(key)
+    } else {
+        let password = match value("password_file") {
+            Some(path) => Password::File(path),
+            None => Password::Value(value("password").unwrap_or_default()),
+        };
+        Auth::Basic {
+            username: value("username").unwrap_or_default(),
+            password,
+        }
+    };
+    Ok(auth)
+}
+
+// Split the --collectors value into individual collector names
+pub fn collectors(opts: &ArgMatches) -> Vec<String> {
+    opts.value_of("collectors")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Parse each --header "Name: value" into a header to send on every ECE request
+pub fn headers(opts: &ArgMatches) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+    opts.values_of("header")
+        .unwrap_or_default()
+        .filter(|h| !h.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("header {} must be in the form 'Name: value'", header))?;
+            let name = HeaderName::from_bytes(name.trim().as_bytes())
+                .map_err(|e| format!("header name {} is not valid: {}", name.trim(), e))?;
+            let value = HeaderValue::from_str(value.trim())
+                .map_err(|e| format!("header value for {} is not valid: {}", name, e))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+// Parse each --tag-as-metric "tag=metric_name" into a map of tag key to metric name
+pub fn tag_metrics(opts: &ArgMatches) -> Result<HashMap<String, String>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut mappings = HashMap::new();
+    for mapping in opts
+        .values_of("tag_as_metric")
+        .unwrap_or_default()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+    {
+        let (tag, metric) = match mapping.split_once('=') {
+            Some((tag, metric)) if !tag.trim().is_empty() => (tag.trim(), metric.trim()),
+            _ => {
+                errors.push(format!(
+                    "tag-as-metric {} must be in the form tag=metric_name",
+                    mapping
+                ));
+                continue;
+            }
+        };
+        if !is_metric_name(metric) {
+            errors.push(format!(
+                "tag-as-metric {} is not a valid metric name",
+                metric
+            ));
+            continue;
+        }
+        mappings.insert(tag.to_string(), metric.to_string());
+    }
+    match errors.is_empty() {
+        true => Ok(mappings),
+        false => Err(errors),
+    }
+}
+
+// Parse each --eru-size-gb-override "configuration_id=GB" into a map of
+// instance configuration to the GB of RAM one ERU buys for it
+fn eru_size_overrides(opts: &ArgMatches) -> Result<HashMap<String, u64>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut overrides = HashMap::new();
+    for mapping in opts
+        .values_of("eru_size_gb_override")
+        .unwrap_or_default()
+        .map(str::trim)
+        .filter(|m| !m.is_empty())
+    {
+        let size = match mapping.split_once('=') {
+            Some((configuration, size)) if !configuration.trim().is_empty() => {
+                (configuration.trim(), size.trim().parse::<u64>())
+            }
+            _ => {
+                errors.push(format!(
+                    "eru-size-gb-override {} must be in the form configuration_id=GB",
+                    mapping
+                ));
+                continue;
+            }
+        };
+        match size {
+            (configuration, Ok(size)) if size > 0 => {
+                overrides.insert(configuration.to_string(), size);
+            }
+            _ => errors.push(format!(
+                "eru-size-gb-override {} must be a whole number of GB greater than zero",
+                mapping
+            )),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(overrides),
+        false => Err(errors),
+    }
+}
+
+// Parse each --instance-label "label=template", rejecting labels given twice
+fn instance_labels(opts: &ArgMatches) -> Result<Vec<LabelTemplate>, Vec<String>> {
+    let mut errors = Vec::new();
+    let mut templates: Vec<LabelTemplate> = Vec::new();
+    for spec in opts
+        .values_of("instance_label")
+        .unwrap_or_default()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        match LabelTemplate::parse(spec) {
+            Ok(template) if templates.iter().any(|t| t.name == template.name) => {
+                errors.push(format!("instance-label {} is given twice", template.name))
+            }
+            Ok(template) => templates.push(template),
+            Err(e) => errors.push(e),
+        }
+    }
+    match errors.is_empty() {
+        true => Ok(templates),
+        false => Err(errors),
+    }
+}
+
+// Prometheus metric names match [a-zA-Z_:][a-zA-Z0-9_:]*
+pub fn is_metric_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == ':' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':')
+        }
+        _ => false,
+    }
+}
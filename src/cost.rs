@@ -0,0 +1,143 @@
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::allocator::Instance;
+use crate::config::CostFormat;
+
+// --eru-cost is quoted per ERU per year
+const SECONDS_PER_YEAR: f64 = 31536000.0;
+
+// Cents per instance series, keyed by its labels
+type CostTotals = HashMap<Vec<(String, String)>, f64>;
+
+// What instances are charged, from the contract's cost and RAM per ERU
+#[derive(Clone, Debug)]
+pub struct CostModel {
+    pub eru_cost: u64,
+    pub format: CostFormat,
+    pub eru_size_gb: u64,
+    pub eru_size_overrides: Arc<HashMap<String, u64>>,
+}
+
+impl CostModel {
+    // Share of an ERU the instance takes up, per the contract's RAM per ERU.
+    // Size of cluster in GB: {{ Cluster size in MB }} / 1024
+    fn erus(&self, instance: &Instance) -> f64 {
+        let size_gb = self
+            .eru_size_overrides
+            .get(&instance.instance_configuration_id)
+            .copied()
+            .unwrap_or(self.eru_size_gb);
+        instance.node_memory as f64 / 1024.0 / size_gb as f64
+    }
+
+    fn cents_per_eru(&self, seconds: f64) -> f64 {
+        self.eru_cost as f64 * 100.0 / SECONDS_PER_YEAR * seconds
+    }
+}
+
+// Cost accumulated by each instance series over the exporter's lifetime, and
+// when it was last brought up to date
+#[derive(Debug, Default)]
+pub struct InstanceCosts {
+    updated: Option<Instant>,
+    cents: CostTotals,
+}
+
+impl InstanceCosts {
+    // Price an allocator collection. Cost accrued since the previous one is
+    // added to each instance's total; nothing accrues on the first.
+    pub fn collection(&mut self, model: &CostModel) -> Collection {
+        // Calculate seconds since month start
+        let now = Utc::now();
+        let month_start = NaiveDate::from_ymd_opt(now.year(), now.month(), 1u32)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap();
+        let month_start_utc = DateTime::<Utc>::from_utc(month_start, Utc);
+        let seconds_since_month_start =
+            now.signed_duration_since(month_start_utc).num_seconds() as f64;
+        log::debug!("\"Seconds in month: {}\"", seconds_since_month_start);
+
+        let cents_per_eru_current_month = model.cents_per_eru(seconds_since_month_start);
+        log::debug!(
+            "\"cents per ERU for current month: {}\"",
+            cents_per_eru_current_month
+        );
+
+        let now = Instant::now();
+        let seconds_since_last_collection = self
+            .updated
+            .map(|updated| now.duration_since(updated).as_secs_f64())
+            .unwrap_or(0.0);
+        self.updated = Some(now);
+
+        Collection {
+            model: model.clone(),
+            cents_per_eru_current_month,
+            cents_per_eru_since_last_collection: model.cents_per_eru(seconds_since_last_collection),
+            previous: Arc::new(std::mem::take(&mut self.cents)),
+            costs: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    // Instances no longer seen stop accruing and are forgotten
+    pub fn finish(&mut self, collection: &Collection) {
+        self.cents = std::mem::take(&mut *collection.costs.lock().unwrap());
+    }
+}
+
+// Prices for one allocator collection, shared by the emitting workers
+#[derive(Clone, Debug)]
+pub struct Collection {
+    model: CostModel,
+    cents_per_eru_current_month: f64,
+    cents_per_eru_since_last_collection: f64,
+    previous: Arc<CostTotals>,
+    costs: Arc<Mutex<CostTotals>>,
+}
+
+impl Collection {
+    // Month to date cost of every instance, in the configured unit
+    pub fn monthly_cost<'a>(&self, instances: impl Iterator<Item = &'a Instance>) -> f64 {
+        let cents: f64 = instances
+            .map(|i| self.model.erus(i) * self.cents_per_eru_current_month)
+            .sum();
+        self.model.format.apply(cents)
+    }
+
+    // Export an instance's cost over the month and over the exporter's lifetime
+    pub fn emit(
+        &self,
+        instance: &Instance,
+        labels: &[(String, String)],
+        node_labels: &[(String, String)],
+    ) {
+        let erus = self.model.erus(instance);
+
+        let cluster_cost_over_month = erus * self.cents_per_eru_current_month;
+
+        // Get instance cost per month
+        metrics::gauge!(
+            "ece_allocator_instance_monthly_cost",
+            self.model.format.apply(cluster_cost_over_month),
+            labels
+        );
+
+        // Unlike the monthly cost, this keeps growing across month boundaries.
+        // Counters are whole numbers, so the running total is kept as a float.
+        let cost_cents = self.previous.get(node_labels).copied().unwrap_or(0.0)
+            + erus * self.cents_per_eru_since_last_collection;
+        metrics::absolute_counter!(
+            "ece_instance_cost_cents_total",
+            cost_cents as u64,
+            node_labels
+        );
+        self.costs
+            .lock()
+            .unwrap()
+            .insert(node_labels.to_vec(), cost_cents);
+    }
+}
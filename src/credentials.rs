@@ -0,0 +1,35 @@
+use http_auth_basic::Credentials as BasicCredentials;
+use hyper::header::HeaderValue;
+
+use crate::session::Session;
+
+// ECE credentials, shared so backends can rotate them at runtime
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub api_key: Option<String>,
+    pub session: Option<Session>,
+}
+
+impl Credentials {
+    // Authorization header value, preferring the api key over basic auth
+    pub fn authorization(&self) -> Option<HeaderValue> {
+        let value = match (&self.api_key, &self.username, &self.password) {
+            (Some(api_key), _, _) => format!("ApiKey {}", api_key),
+            (None, Some(username), Some(password)) => {
+                BasicCredentials::new(username, password).as_http_header()
+            }
+            _ => return None,
+        };
+        Some(HeaderValue::from_str(&value).expect("failed to convert credential header"))
+    }
+
+    // Authorization header to send, using the session token once logged in
+    pub fn request_authorization(&self) -> Option<HeaderValue> {
+        match &self.session {
+            Some(session) => Some(session.authorization()),
+            None => self.authorization(),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashSet};
+
+use crate::collector::{Collection, Collector};
+use crate::config::is_metric_name;
+use crate::error::Error as RestError;
+use crate::state::{State, DEPLOYMENT_COLLECTORS};
+use crate::template::is_label_name;
+
+// Label value used when a label's selector matches nothing
+const MISSING_LABEL: &str = "null";
+
+// One step into a JSON document
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Key(String),
+    Index(usize),
+    All,
+}
+
+// JSONPath/jq style path into an ECE response: an optional $, then any of
+// .key, ["key"], [N], and [*] (or .* or []) for every element of an array or
+// every value of an object
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+pub struct Selector {
+    steps: Vec<Step>,
+}
+
+impl TryFrom<String> for Selector {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        Selector::parse(&source)
+    }
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut rest = source.trim();
+        rest = rest.strip_prefix('$').unwrap_or(rest);
+        // A lone . is the document itself
+        if rest == "." {
+            rest = "";
+        }
+        let mut steps = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('[') {
+                let end = after
+                    .find(']')
+                    .ok_or_else(|| format!("selector {} has an unclosed [", source))?;
+                let inner = after[..end].trim();
+                let quoted = inner.len() >= 2
+                    && ((inner.starts_with('"') && inner.ends_with('"'))
+                        || (inner.starts_with('\'') && inner.ends_with('\'')));
+                steps.push(match inner {
+                    "" | "*" => Step::All,
+                    _ if quoted => Step::Key(inner[1..inner.len() - 1].to_string()),
+                    index => Step::Index(index.parse().map_err(|_| {
+                        format!("selector {} has an invalid index [{}]", source, index)
+                    })?),
+                });
+                rest = &after[end + 1..];
+            } else if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                match &after[..end] {
+                    // jq writes .[0] where JSONPath writes [0]
+                    "" if after.starts_with('[') => {}
+                    "" => return Err(format!("selector {} has an empty key", source)),
+                    "*" => steps.push(Step::All),
+                    key => steps.push(Step::Key(key.to_string())),
+                }
+                rest = &after[end..];
+            } else {
+                return Err(format!(
+                    "selector {} must be made of .key, [\"key\"], [N] or [*] steps",
+                    source
+                ));
+            }
+        }
+        Ok(Selector { steps })
+    }
+
+    // Every value the path leads to, in document order
+    pub fn select<'a>(&self, document: &'a Value) -> Vec<&'a Value> {
+        let mut found = vec![document];
+        for step in &self.steps {
+            found = found
+                .into_iter()
+                .flat_map(|value| match (step, value) {
+                    (Step::Key(key), _) => value.get(key).into_iter().collect(),
+                    (Step::Index(index), _) => value.get(index).into_iter().collect(),
+                    (Step::All, Value::Array(items)) => items.iter().collect(),
+                    (Step::All, Value::Object(fields)) => fields.values().collect(),
+                    (Step::All, _) => Vec::new(),
+                })
+                .collect();
+        }
+        found
+    }
+
+    fn first<'a>(&self, document: &'a Value) -> Option<&'a Value> {
+        self.select(document).into_iter().next()
+    }
+}
+
+// Gauge exported for every item a selector matches
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CustomMetric {
+    pub name: String,
+    pub select: Selector,
+    // Relative to the item, which is itself the value when left out
+    #[serde(default)]
+    pub value: Option<Selector>,
+    // Label name to selector relative to the item
+    #[serde(default)]
+    pub labels: BTreeMap<String, Selector>,
+}
+
+// An ECE API path turned into the gauges described by a --custom-metrics file
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CustomCollector {
+    pub name: String,
+    pub path: String,
+    pub metrics: Vec<CustomMetric>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CustomFile {
+    collectors: Vec<CustomCollector>,
+}
+
+impl CustomCollector {
+    // Load and check a --custom-metrics file, whose collector names must not
+    // clash with each other or with the built-in ones
+    pub fn load(path: &str, builtin: &[&str]) -> Result<Vec<Self>, Vec<String>> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| vec![format!("custom metrics {} is not readable: {}", path, e)])?;
+        let file: CustomFile = serde_yaml::from_str(&contents)
+            .map_err(|e| vec![format!("custom metrics {} is not valid: {}", path, e)])?;
+
+        let mut errors = Vec::new();
+        let mut names = HashSet::new();
+        let mut collectors = file.collectors;
+        for collector in &mut collectors {
+            if collector.name.trim().is_empty() {
+                errors.push(format!(
+                    "custom metrics {} has a collector without a name",
+                    path
+                ));
+            } else if builtin.contains(&collector.name.as_str())
+                || DEPLOYMENT_COLLECTORS.contains(&collector.name.as_str())
+            {
+                // Also reserved when the deployment collectors aren't built in,
+                // so a custom collector can't take the name of one in another build
+                errors.push(format!(
+                    "custom collector {} has the name of a built-in collector",
+                    collector.name
+                ));
+            } else if !names.insert(collector.name.clone()) {
+                errors.push(format!(
+                    "custom collector {} is defined twice",
+                    collector.name
+                ));
+            }
+            // Paths are relative to --url, like the built-in ones
+            collector.path = collector.path.trim_start_matches('/').to_string();
+            for metric in &collector.metrics {
+                if !is_metric_name(&metric.name) {
+                    errors.push(format!(
+                        "custom collector {} metric {} is not a valid metric name",
+                        collector.name, metric.name
+                    ));
+                }
+                for label in metric.labels.keys() {
+                    if !is_label_name(label) {
+                        errors.push(format!(
+                            "custom collector {} label {} is not a valid label name",
+                            collector.name, label
+                        ));
+                    }
+                }
+            }
+        }
+        match errors.is_empty() {
+            true => Ok(collectors),
+            false => Err(errors),
+        }
+    }
+}
+
+// Numbers are exported as is, booleans as 1 or 0 and numeric strings parsed
+fn number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::Bool(flag) => Some(if *flag { 1f64 } else { 0f64 }),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn label(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Null) | None => MISSING_LABEL.to_string(),
+        Some(other) => other.to_string(),
+    }
+}
+
+#[async_trait]
+impl Collector for CustomCollector {
+    type Payload = Value;
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    async fn fetch(
+        &self,
+        state: &State,
+        _collection: &Collection,
+    ) -> Result<Self::Payload, RestError> {
+        state.get_json(&self.path).await
+    }
+
+    // Items without a numeric value are skipped rather than failing the collector
+    async fn emit(&self, _state: &State, payload: Self::Payload) -> Result<(), RestError> {
+        for metric in &self.metrics {
+            for item in metric.select.select(&payload) {
+                let value = match &metric.value {
+                    Some(selector) => selector.first(item),
+                    None => Some(item),
+                };
+                let Some(value) = value.and_then(number) else {
+                    log::debug!(
+                        "\"Custom metric {} skipped a non-numeric value\"",
+                        metric.name
+                    );
+                    continue;
+                };
+                let labels: Vec<(String, String)> = metric
+                    .labels
+                    .iter()
+                    .map(|(name, selector)| (name.clone(), label(selector.first(item))))
+                    .collect();
+                metrics::gauge!(metric.name.clone(), value, &labels);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn selectors_walk_keys_indexes_and_wildcards() {
+        let document = json!({
+            "proxies": [
+                {"proxy_id": "p1", "metadata": {"zone": "z1"}, "healthy": true},
+                {"proxy_id": "p2", "metadata": {"zone": "z2"}, "healthy": false}
+            ]
+        });
+        let select = |source: &str| Selector::parse(source).unwrap().select(&document);
+
+        assert_eq!(select("$.proxies[*].proxy_id"), vec!["p1", "p2"]);
+        assert_eq!(select(".proxies[].metadata.zone"), vec!["z1", "z2"]);
+        assert_eq!(select("$['proxies'][1].healthy"), vec![false]);
+        assert_eq!(select(".proxies.[0].metadata.*"), vec!["z1"]);
+        assert_eq!(select(".").len(), 1);
+        assert!(select(".proxies[5]").is_empty());
+        assert!(Selector::parse("proxies").is_err());
+        assert!(Selector::parse(".proxies[x]").is_err());
+        assert!(Selector::parse(".proxies..id").is_err());
+    }
+
+    #[test]
+    fn collectors_cannot_reuse_built_in_names() {
+        let path = std::env::temp_dir().join(format!("ece-custom-{}.yaml", std::process::id()));
+        let file =
+            "collectors:\n  - name: endpoints\n    path: api/v1/deployments\n    metrics: []\n";
+        std::fs::write(&path, file).unwrap();
+        let errors = CustomCollector::load(path.to_str().unwrap(), &[]).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            errors,
+            vec!["custom collector endpoints has the name of a built-in collector"]
+        );
+    }
+}
@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "deployments")]
+use serde_json::Value;
+#[cfg(feature = "deployments")]
+use std::collections::HashMap;
+
+#[cfg(feature = "deployments")]
+use crate::elasticsearch::PlanInfo;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeploymentsListRoot {
+    pub deployments: Vec<DeploymentListing>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeploymentListing {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub resources: Vec<ListedResource>,
+}
+
+// Resource of a listed deployment, e.g. its Elasticsearch or Kibana cluster
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ListedResource {
+    pub id: String,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeploymentsRoot {
+    pub deployments: Vec<Deployment>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Deployment {
+    pub id: String,
+    pub name: String,
+    pub healthy: Option<bool>,
+    #[serde(default)]
+    pub resources: Resources,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Resources {
+    #[serde(default)]
+    pub elasticsearch: Vec<ResourceRef>,
+    #[serde(default)]
+    pub kibana: Vec<ResourceRef>,
+    #[serde(default)]
+    pub apm: Vec<Resource>,
+    #[serde(default)]
+    pub integrations_server: Vec<Resource>,
+    #[serde(default)]
+    pub enterprise_search: Vec<Resource>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Resource {
+    pub ref_id: String,
+    pub id: String,
+    pub info: ResourceInfo,
+}
+
+// Just enough of a resource to address its sub-resources
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResourceRef {
+    pub ref_id: String,
+    pub id: String,
+    #[serde(default)]
+    pub info: Option<EndpointInfo>,
+}
+
+// Where the proxies serve a resource
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EndpointInfo {
+    pub metadata: Option<EndpointMetadata>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EndpointMetadata {
+    pub service_url: Option<String>,
+    pub aliased_url: Option<String>,
+}
+
+#[cfg(feature = "deployments")]
+impl ResourceRef {
+    // The deployment alias when enabled, as users reach it, else the resource's own URL
+    pub fn url(&self) -> Option<&str> {
+        let metadata = self.info.as_ref()?.metadata.as_ref()?;
+        metadata
+            .aliased_url
+            .as_deref()
+            .or(metadata.service_url.as_deref())
+    }
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResourceInfo {
+    pub healthy: bool,
+    pub status: String,
+    pub plan_info: Option<PlanInfo>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteResources {
+    #[serde(default)]
+    pub resources: Vec<RemoteResource>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteResource {
+    pub alias: String,
+    pub deployment_id: String,
+    pub elasticsearch_ref_id: String,
+    #[serde(default)]
+    pub skip_unavailable: bool,
+    pub info: Option<RemoteResourceInfo>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RemoteResourceInfo {
+    #[serde(default)]
+    pub healthy: bool,
+    #[serde(default)]
+    pub connected: bool,
+    #[serde(default)]
+    pub compatible: bool,
+    pub version: Option<String>,
+}
+
+#[cfg(feature = "deployments")]
+impl Resources {
+    // Stateless resources monitored by the deployments collector, with their kind
+    pub fn monitored(&self) -> Vec<(&'static str, &Resource)> {
+        let apm = self.apm.iter().map(|r| ("apm", r));
+        let integrations_server = self
+            .integrations_server
+            .iter()
+            .map(|r| ("integrations_server", r));
+        let enterprise_search = self
+            .enterprise_search
+            .iter()
+            .map(|r| ("enterprise_search", r));
+        apm.chain(integrations_server)
+            .chain(enterprise_search)
+            .collect()
+    }
+
+    // Resources with a public URL probed by the endpoints collector
+    pub fn endpoints(&self) -> Vec<(&'static str, &ResourceRef)> {
+        let elasticsearch = self.elasticsearch.iter().map(|r| ("elasticsearch", r));
+        let kibana = self.kibana.iter().map(|r| ("kibana", r));
+        elasticsearch.chain(kibana).collect()
+    }
+}
+
+#[cfg(feature = "deployments")]
+impl Resource {
+    // Version from the current plan, which is keyed by the resource kind
+    pub fn version(&self, kind: &str) -> Option<&str> {
+        let plan: &Value = self
+            .info
+            .plan_info
+            .as_ref()?
+            .current
+            .as_ref()?
+            .plan
+            .as_ref()?;
+        plan[kind]["version"].as_str()
+    }
+}
+
+// The _cluster/health fields exported by the cluster_health collector
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ClusterHealth {
+    pub status: String,
+    #[serde(default)]
+    pub number_of_nodes: u64,
+    #[serde(default)]
+    pub relocating_shards: u64,
+    #[serde(default)]
+    pub initializing_shards: u64,
+    #[serde(default)]
+    pub unassigned_shards: u64,
+}
+
+// A row of _cat/allocation?format=json&bytes=b, which gives numbers as strings
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CatAllocation {
+    pub node: String,
+    pub shards: Option<String>,
+    #[serde(rename = "disk.used")]
+    pub disk_used: Option<String>,
+}
+
+#[cfg(feature = "deployments")]
+impl CatAllocation {
+    pub fn shards(&self) -> Option<f64> {
+        self.shards.as_deref()?.parse().ok()
+    }
+
+    pub fn disk_used(&self) -> Option<f64> {
+        self.disk_used.as_deref()?.parse().ok()
+    }
+}
+
+// _nodes/stats/jvm, filtered down to each node's name and heap
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NodesStats {
+    #[serde(default)]
+    pub nodes: HashMap<String, NodeStats>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NodeStats {
+    pub name: String,
+    pub jvm: Option<JvmStats>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JvmStats {
+    pub mem: JvmMemory,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JvmMemory {
+    #[serde(default)]
+    pub heap_used_in_bytes: u64,
+    #[serde(default)]
+    pub heap_max_in_bytes: u64,
+}
+
+// _resolve/index, keeping only the names, which are all that is counted
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedIndices {
+    #[serde(default)]
+    pub indices: Vec<ResolvedName>,
+    #[serde(default)]
+    pub data_streams: Vec<ResolvedName>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedName {
+    pub name: String,
+}
+
+// A policy from _slm/policy, keyed by its id
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlmPolicy {
+    pub last_success: Option<SlmInvocation>,
+    pub last_failure: Option<SlmInvocation>,
+}
+
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SlmInvocation {
+    // Milliseconds since the epoch
+    pub time: i64,
+}
+
+// _ilm/explain, filtered down to the policy of each index
+#[cfg(feature = "deployments")]
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IlmExplain {
+    // Left out altogether when no index matched
+    #[serde(default)]
+    pub indices: HashMap<String, Value>,
+}
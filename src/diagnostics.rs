@@ -0,0 +1,149 @@
+use chrono::Utc;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::state::State;
+
+// Summary of the most recent collection, kept for the SIGUSR1 state dump
+// and /summary
+#[derive(Debug, Default, Serialize)]
+pub struct Diagnostics {
+    pub zones: usize,
+    pub allocators: usize,
+    pub allocators_unhealthy: usize,
+    pub instances: usize,
+    pub proxies: usize,
+    pub proxies_unhealthy: usize,
+    pub memory_total_bytes: u64,
+    pub memory_used_bytes: u64,
+    pub monthly_cost: Option<f64>,
+    pub last_collect: Option<String>,
+    pub last_errors: BTreeMap<String, LastError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastError {
+    pub error: String,
+    pub at: String,
+}
+
+impl Diagnostics {
+    pub fn record_error(&mut self, collector: &str, error: String) {
+        let at = Utc::now().to_rfc3339();
+        self.last_errors
+            .insert(collector.to_string(), LastError { error, at });
+    }
+}
+
+// Effective configuration, without any credentials
+pub fn config(state: &State) -> Value {
+    let credentials = state.credentials.read().unwrap();
+    let auth = match (&credentials.api_key, &credentials.username) {
+        (Some(_), _) => "apikey",
+        (None, Some(_)) if state.session_auth => "session",
+        (None, Some(_)) => "basic",
+        (None, None) => "none",
+    };
+    #[cfg(feature = "push")]
+    let webhook = state.notifier.is_some();
+    #[cfg(not(feature = "push"))]
+    let webhook = false;
+    let headers: Vec<&str> = state
+        .headers
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect();
+    let instance_labels: BTreeMap<&str, &str> = state
+        .instance_labels
+        .iter()
+        .map(|t| (t.name.as_str(), t.template.as_str()))
+        .collect();
+    json!({
+        "url": state.url,
+        "platform_version": *state.platform_version.lock().unwrap(),
+        "auth": auth,
+        "collectors": state.collectors,
+        "eru_cost": state.eru_cost,
+        "eru_size_gb": state.eru_size_gb,
+        "eru_size_overrides": state.eru_size_overrides.as_ref(),
+        "cost_unit": state.cost_format.unit.as_str(),
+        "cost_precision": state.cost_format.precision,
+        "max_response_bytes": state.max_response_bytes,
+        "max_concurrent_requests": state.max_concurrent_requests,
+        "max_concurrent_scrapes": state.max_concurrent_scrapes,
+        "mb_metrics": state.mb_metrics,
+        "allocator_label": state.allocator_label.as_str(),
+        "aggregate_only": state.aggregate_only,
+        "deployment_query": state.deployment_query.as_ref(),
+        "probe_timeout": state.probe_timeout.as_secs(),
+        "tag_metrics": state.tag_metrics.as_ref(),
+        "instance_labels": instance_labels,
+        "name_cache_ttl": state.name_cache_ttl.as_secs(),
+        "stale_while_revalidate": state.stale_while_revalidate.as_secs(),
+        "request_timeout": state.client.request_timeout().as_secs(),
+        "server_timeout": state.server_timeout.as_secs(),
+        "headers": headers,
+        "user_agent": state.user_agent.to_str().unwrap_or_default(),
+        "snapshot_file": state.snapshot.is_some(),
+        "webhook": webhook,
+    })
+}
+
+// Compact platform digest for chat-ops bots and status pages
+pub fn summary(state: &State) -> Value {
+    let diagnostics = state.diagnostics.lock().unwrap();
+    json!({
+        "zones": diagnostics.zones,
+        "allocators": {
+            "total": diagnostics.allocators,
+            "unhealthy": diagnostics.allocators_unhealthy,
+        },
+        "proxies": {
+            "total": diagnostics.proxies,
+            "unhealthy": diagnostics.proxies_unhealthy,
+        },
+        "instances": diagnostics.instances,
+        "memory": {
+            "total_bytes": diagnostics.memory_total_bytes,
+            "used_bytes": diagnostics.memory_used_bytes,
+        },
+        "monthly_cost": diagnostics.monthly_cost.map(|value| json!({
+            "value": value,
+            "unit": state.cost_format.unit.as_str(),
+        })),
+        "last_collect": diagnostics.last_collect,
+    })
+}
+
+// Log the current snapshot summary and effective configuration as one JSON line
+pub fn dump(state: &State) {
+    let dump = json!({
+        "event": "state dump",
+        "snapshot": &*state.diagnostics.lock().unwrap(),
+        "config": config(state),
+    });
+    log::info!("{}", dump);
+}
+
+// Dump state to the log whenever the process receives SIGUSR1
+#[cfg(unix)]
+pub fn spawn_signal_handler(state: State) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut signals = match signal(SignalKind::user_defined1()) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("{{\"error\": \"unable to listen for SIGUSR1: {}\"}}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        while signals.recv().await.is_some() {
+            dump(&state);
+        }
+    });
+}
+
+#[cfg(not(unix))]
+pub fn spawn_signal_handler(_state: State) {}
@@ -0,0 +1,240 @@
+use core::time::Duration;
+use metrics::{Counter, CounterFn, Gauge, GaugeFn, Histogram, Key, KeyName, Label, Recorder, Unit};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// How the values of series that only differed by a dropped label are combined
+#[derive(Clone, Copy)]
+enum Merge {
+    Sum,
+    Max,
+    Min,
+    Mean,
+}
+
+impl Merge {
+    // Picked from the metric name, summing unless the name says otherwise
+    fn for_metric(name: &str) -> Self {
+        if name.ends_with("_info") || name.ends_with("_max") {
+            Merge::Max
+        } else if name.ends_with("_min") {
+            Merge::Min
+        } else if name.ends_with("_ratio") || name.ends_with("_avg") {
+            Merge::Mean
+        } else {
+            Merge::Sum
+        }
+    }
+
+    fn apply(&self, values: impl Iterator<Item = f64>) -> f64 {
+        let values: Vec<f64> = values.collect();
+        match self {
+            Merge::Sum => values.iter().sum(),
+            Merge::Max => values.iter().cloned().fold(f64::MIN, f64::max),
+            Merge::Min => values.iter().cloned().fold(f64::MAX, f64::min),
+            Merge::Mean => values.iter().sum::<f64>() / values.len().max(1) as f64,
+        }
+    }
+}
+
+// Series merged into one by dropping labels, with the value and update time
+// of each source series
+struct Merged {
+    merge: Merge,
+    sources: Mutex<HashMap<Key, (f64, Instant)>>,
+}
+
+// Recorder removing the --drop-label labels from every series before handing
+// it on. Series left with the same labels are merged into one.
+pub struct DropLabelsRecorder {
+    inner: Box<dyn Recorder>,
+    labels: Vec<String>,
+    idle_timeout: Duration,
+    merged: Mutex<HashMap<Key, Arc<Merged>>>,
+}
+
+impl DropLabelsRecorder {
+    pub fn new(inner: Box<dyn Recorder>, labels: Vec<String>, idle_timeout: Duration) -> Self {
+        DropLabelsRecorder {
+            inner,
+            labels,
+            idle_timeout,
+            merged: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // The key without the dropped labels, or None when it has none of them
+    fn strip(&self, key: &Key) -> Option<Key> {
+        if !key
+            .labels()
+            .any(|l| self.labels.iter().any(|d| d == l.key()))
+        {
+            return None;
+        }
+        let labels: Vec<Label> = key
+            .labels()
+            .filter(|l| !self.labels.iter().any(|d| d == l.key()))
+            .cloned()
+            .collect();
+        Some(Key::from_parts(key.name().to_string(), labels))
+    }
+
+    fn merged(&self, key: &Key) -> Arc<Merged> {
+        self.merged
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_insert_with(|| {
+                Arc::new(Merged {
+                    merge: Merge::for_metric(key.name()),
+                    sources: Mutex::new(HashMap::new()),
+                })
+            })
+            .clone()
+    }
+}
+
+impl Recorder for DropLabelsRecorder {
+    fn describe_counter(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_counter(key, unit, description)
+    }
+
+    fn describe_gauge(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_gauge(key, unit, description)
+    }
+
+    fn describe_histogram(&self, key: KeyName, unit: Option<Unit>, description: &'static str) {
+        self.inner.describe_histogram(key, unit, description)
+    }
+
+    fn register_counter(&self, key: &Key) -> Counter {
+        match self.strip(key) {
+            Some(stripped) => Counter::from_arc(Arc::new(SourceCounter {
+                source: key.clone(),
+                merged: self.merged(&stripped),
+                inner: self.inner.register_counter(&stripped),
+            })),
+            None => self.inner.register_counter(key),
+        }
+    }
+
+    fn register_gauge(&self, key: &Key) -> Gauge {
+        match self.strip(key) {
+            Some(stripped) => Gauge::from_arc(Arc::new(SourceGauge {
+                source: key.clone(),
+                merged: self.merged(&stripped),
+                inner: self.inner.register_gauge(&stripped),
+                idle_timeout: self.idle_timeout,
+            })),
+            None => self.inner.register_gauge(key),
+        }
+    }
+
+    // Observations of every source land in the same histogram
+    fn register_histogram(&self, key: &Key) -> Histogram {
+        match self.strip(key) {
+            Some(stripped) => self.inner.register_histogram(&stripped),
+            None => self.inner.register_histogram(key),
+        }
+    }
+}
+
+struct SourceCounter {
+    source: Key,
+    merged: Arc<Merged>,
+    inner: Counter,
+}
+
+impl CounterFn for SourceCounter {
+    fn increment(&self, value: u64) {
+        self.inner.increment(value)
+    }
+
+    // Counters never forget a source, which would make the total go backwards
+    fn absolute(&self, value: u64) {
+        let mut sources = self.merged.sources.lock().unwrap();
+        sources.insert(self.source.clone(), (value as f64, Instant::now()));
+        self.inner
+            .absolute(sources.values().map(|(v, _)| v).sum::<f64>() as u64)
+    }
+}
+
+struct SourceGauge {
+    source: Key,
+    merged: Arc<Merged>,
+    inner: Gauge,
+    idle_timeout: Duration,
+}
+
+impl SourceGauge {
+    // Sources gone idle no longer count, as they would have expired unmerged
+    fn update(&self, change: impl FnOnce(f64) -> f64) {
+        let mut sources = self.merged.sources.lock().unwrap();
+        let previous = sources.get(&self.source).map(|(v, _)| *v).unwrap_or(0.0);
+        sources.insert(self.source.clone(), (change(previous), Instant::now()));
+        sources.retain(|_, (_, updated)| updated.elapsed() < self.idle_timeout);
+        self.inner
+            .set(self.merged.merge.apply(sources.values().map(|(v, _)| *v)))
+    }
+}
+
+impl GaugeFn for SourceGauge {
+    fn increment(&self, value: f64) {
+        self.update(|previous| previous + value)
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|previous| previous - value)
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn series_left_identical_are_merged() {
+        let inner = DebuggingRecorder::new();
+        let snapshotter = inner.snapshotter();
+        let recorder = DropLabelsRecorder::new(
+            Box::new(inner),
+            vec!["ip".to_string()],
+            Duration::from_secs(60),
+        );
+        for (ip, memory, ratio) in [("a", 1.0, 0.2), ("b", 2.0, 0.4)] {
+            let labels = vec![Label::new("zone", "z1"), Label::new("ip", ip)];
+            let key = Key::from_parts("ece_allocator_memory_total", labels.clone());
+            recorder.register_gauge(&key).set(memory);
+            let key = Key::from_parts("ece_zone_memory_utilization_ratio", labels);
+            recorder.register_gauge(&key).set(ratio);
+        }
+
+        let mut values: Vec<(String, Vec<String>, f64)> = snapshotter
+            .snapshot()
+            .into_vec()
+            .into_iter()
+            .map(|(key, _, _, value)| {
+                let key = key.key().clone();
+                let labels = key.labels().map(|l| l.key().to_string()).collect();
+                match value {
+                    DebugValue::Gauge(value) => {
+                        (key.name().to_string(), labels, value.into_inner())
+                    }
+                    _ => unreachable!(),
+                }
+            })
+            .collect();
+        values.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(values.len(), 2);
+        assert_eq!(values[0].0, "ece_allocator_memory_total");
+        assert_eq!(values[0].1, vec!["zone"]);
+        assert_eq!(values[0].2, 3.0);
+        assert!((values[1].2 - 0.3).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElasticsearchClustersRoot {
+    pub elasticsearch_clusters: Vec<ElasticsearchCluster>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElasticsearchCluster {
+    pub cluster_id: String,
+    pub cluster_name: String,
+    pub deployment_id: Option<String>,
+    pub healthy: bool,
+    pub status: String,
+    pub elasticsearch: Option<ElasticsearchInfo>,
+    pub topology: Option<Topology>,
+    pub plan_info: Option<PlanInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElasticsearchInfo {
+    pub healthy: bool,
+    pub shard_info: Option<ShardInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShardInfo {
+    pub healthy: bool,
+    #[serde(default)]
+    pub available_shards: Vec<InstanceShards>,
+    #[serde(default)]
+    pub unavailable_shards: Vec<InstanceShards>,
+    #[serde(default)]
+    pub unavailable_replicas: Vec<InstanceReplicas>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceShards {
+    pub instance_name: String,
+    pub shard_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceReplicas {
+    pub instance_name: String,
+    pub replica_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Topology {
+    pub healthy: bool,
+    #[serde(default)]
+    pub instances: Vec<TopologyInstance>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopologyInstance {
+    pub instance_name: String,
+    pub healthy: bool,
+    pub zone: Option<String>,
+    pub instance_configuration: Option<InstanceConfigurationInfo>,
+    pub memory: Option<InstanceMemory>,
+    pub service_version: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceConfigurationInfo {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceMemory {
+    pub instance_capacity: u64,
+}
+
+// What the plan asks of a tier against what is actually running, in MB and zones
+#[derive(Debug, Default, PartialEq)]
+pub struct TierState {
+    pub desired_memory: u64,
+    pub desired_zones: u64,
+    pub actual_memory: u64,
+    pub actual_zones: BTreeSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanInfo {
+    pub current: Option<PlanAttempt>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlanAttempt {
+    pub plan: Option<Value>,
+}
+
+impl ElasticsearchCluster {
+    // Derive the familiar green/yellow/red status from the shard info
+    pub fn color(&self) -> &'static str {
+        let shard_info = match self
+            .elasticsearch
+            .as_ref()
+            .and_then(|e| e.shard_info.as_ref())
+        {
+            Some(shard_info) => shard_info,
+            None => return "unknown",
+        };
+        if shard_info
+            .unavailable_shards
+            .iter()
+            .any(|s| s.shard_count > 0)
+        {
+            "red"
+        } else if shard_info
+            .unavailable_replicas
+            .iter()
+            .any(|r| r.replica_count > 0)
+        {
+            "yellow"
+        } else {
+            "green"
+        }
+    }
+
+    // The current plan, when one has been applied
+    pub fn plan(&self) -> Option<&Value> {
+        self.plan_info.as_ref()?.current.as_ref()?.plan.as_ref()
+    }
+
+    // Desired and running capacity per instance configuration. Tiers only the
+    // plan or only the topology knows about are included with zeros for the other.
+    pub fn tiers(&self) -> BTreeMap<String, TierState> {
+        let mut tiers: BTreeMap<String, TierState> = BTreeMap::new();
+        if let Some(elements) = self
+            .plan()
+            .and_then(|plan| plan["cluster_topology"].as_array())
+        {
+            for element in elements {
+                let configuration = element["instance_configuration_id"]
+                    .as_str()
+                    .unwrap_or("null");
+                let size = element["size"]["value"]
+                    .as_u64()
+                    .or_else(|| element["memory_per_node"].as_u64())
+                    .unwrap_or(0);
+                let zones = element["zone_count"].as_u64().unwrap_or(0);
+                let tier = tiers.entry(configuration.to_string()).or_default();
+                // Sizes are per zone
+                tier.desired_memory += size * zones;
+                tier.desired_zones = tier.desired_zones.max(zones);
+            }
+        }
+        for instance in self.topology.iter().flat_map(|t| &t.instances) {
+            let configuration = match &instance.instance_configuration {
+                Some(configuration) => configuration.id.clone(),
+                None => continue,
+            };
+            let tier = tiers.entry(configuration).or_default();
+            tier.actual_memory += instance
+                .memory
+                .as_ref()
+                .map(|m| m.instance_capacity)
+                .unwrap_or(0);
+            if let Some(zone) = &instance.zone {
+                tier.actual_zones.insert(zone.clone());
+            }
+        }
+        tiers
+    }
+}
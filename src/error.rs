@@ -1,18 +1,29 @@
 //use serde_json::error::Error as SerdeError;
 use axum::{
-    body::{self},
-    http::StatusCode,
+    body::Body,
+    http::{header::RETRY_AFTER, StatusCode},
     response::{IntoResponse, Response},
 };
+use serde_json::json;
 use std::fmt;
 
 #[derive(Debug)]
 pub enum Error {
-    Forbidden,
-    Unauthorized,
-    NotFound,
-    UnknownCode,
+    Api {
+        status: u16,
+        code: String,
+        message: String,
+    },
+    InternalServer(String),
+    BadGateway(String),
+    ServiceUnavailable(String),
+    GatewayTimeout(String),
+    RateLimited(u64),
+    AuthCooldown(u64),
+    ResponseTooLarge(u64),
+    Overloaded(u64),
     Hyper(hyper::Error),
+    Client(hyper_util::client::legacy::Error),
     SerdeJson(serde_json::Error),
 }
 
@@ -21,11 +32,56 @@ impl std::error::Error for Error {}
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::Forbidden => f.write_str("{\"error\": \"Cannot get config: Forbidden\"}"),
-            Error::Unauthorized => f.write_str("{\"error\": \"Cannot get config: Unauthorized\"}"),
-            Error::NotFound => f.write_str("{\"error\": \"Cannot get config: Not found\"}"),
-            Error::UnknownCode => f.write_str("{\"error\": \"Caught bad status code\"}"),
+            Error::Api {
+                status,
+                ref code,
+                ref message,
+            } => write!(
+                f,
+                "{}",
+                json!({"error": "ECE API error", "status": status, "code": code, "message": message})
+            ),
+            Error::InternalServer(ref message) => write!(
+                f,
+                "{}",
+                json!({"error": "ECE internal server error", "message": message})
+            ),
+            Error::BadGateway(ref message) => write!(
+                f,
+                "{}",
+                json!({"error": "ECE bad gateway", "message": message})
+            ),
+            Error::ServiceUnavailable(ref message) => write!(
+                f,
+                "{}",
+                json!({"error": "ECE service unavailable", "message": message})
+            ),
+            Error::GatewayTimeout(ref message) => write!(
+                f,
+                "{}",
+                json!({"error": "ECE gateway timeout", "message": message})
+            ),
+            Error::RateLimited(secs) => write!(
+                f,
+                "{{\"error\": \"Rate limited by ECE, backing off for {}s\"}}",
+                secs
+            ),
+            Error::AuthCooldown(secs) => write!(
+                f,
+                "{{\"error\": \"ECE rejected the credentials, retrying in {}s\"}}",
+                secs
+            ),
+            Error::ResponseTooLarge(max) => write!(
+                f,
+                "{{\"error\": \"ECE response exceeded the maximum size of {} bytes\"}}",
+                max
+            ),
+            Error::Overloaded(_) => write!(
+                f,
+                "{{\"error\": \"Too many concurrent scrapes, try again later\"}}"
+            ),
             Error::Hyper(ref err) => write!(f, "{{\"error\": \"{}\"}}", err),
+            Error::Client(ref err) => write!(f, "{{\"error\": \"{}\"}}", err),
             Error::SerdeJson(ref err) => write!(f, "{{\"error\": \"{}\"}}", err),
         }
     }
@@ -33,13 +89,24 @@ impl fmt::Display for Error {
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
+        let status = match self {
+            Error::Api { status: 404, .. } => StatusCode::NOT_FOUND,
+            Error::Api { .. } => StatusCode::BAD_GATEWAY,
+            Error::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            Error::InternalServer(_) | Error::BadGateway(_) => StatusCode::BAD_GATEWAY,
+            Error::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::GatewayTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::ResponseTooLarge(_) => StatusCode::BAD_GATEWAY,
+            Error::AuthCooldown(_) => StatusCode::BAD_GATEWAY,
+            Error::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut builder = Response::builder().status(status);
+        if let Error::RateLimited(secs) | Error::Overloaded(secs) = self {
+            builder = builder.header(RETRY_AFTER, secs);
+        }
         let payload = self.to_string();
-        let body = body::boxed(body::Full::from(payload));
-
-        Response::builder()
-            .status(StatusCode::INTERNAL_SERVER_ERROR)
-            .body(body)
-            .unwrap()
+        builder.body(Body::from(payload)).unwrap()
     }
 }
 
@@ -49,8 +116,53 @@ impl From<hyper::Error> for Error {
     }
 }
 
+impl From<hyper_util::client::legacy::Error> for Error {
+    fn from(err: hyper_util::client::legacy::Error) -> Error {
+        Error::Client(err)
+    }
+}
+
 impl From<serde_json::Error> for Error {
     fn from(err: serde_json::Error) -> Error {
         Error::SerdeJson(err)
     }
 }
+
+impl Error {
+    // Map a failed ECE status code and its parsed error to a variant
+    pub fn from_status(status: u16, code: String, message: String) -> Error {
+        match status {
+            500 => Error::InternalServer(message),
+            502 => Error::BadGateway(message),
+            503 => Error::ServiceUnavailable(message),
+            504 => Error::GatewayTimeout(message),
+            _ => Error::Api {
+                status,
+                code,
+                message,
+            },
+        }
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        matches!(
+            self,
+            Error::InternalServer(_)
+                | Error::BadGateway(_)
+                | Error::ServiceUnavailable(_)
+                | Error::GatewayTimeout(_)
+        )
+    }
+}
+
+// Extract the first errors[].code and message from the body of a failed ECE response
+pub fn parse_api_errors(bytes: &[u8]) -> (String, String) {
+    let value: serde_json::Value = serde_json::from_slice(bytes).unwrap_or_default();
+    let first = &value["errors"][0];
+    let code = first["code"].as_str().unwrap_or("unknown").to_string();
+    let message = match first["message"].as_str() {
+        Some(message) => message.to_string(),
+        None => String::from_utf8_lossy(bytes).chars().take(512).collect(),
+    };
+    (code, message)
+}
@@ -0,0 +1,223 @@
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::allocator::AllocatorsRoot;
+use crate::config::AllocatorLabel;
+use crate::proxy::ProxiesRoot;
+
+// Transitions kept for the /events endpoint, oldest dropped first
+const EVENT_BUFFER: usize = 256;
+
+// A health state that flipped between two collections
+#[derive(Serialize, Debug, Clone)]
+pub struct Event {
+    pub time: String,
+    pub zone: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allocator: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cluster_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance_name: Option<String>,
+    pub state: &'static str,
+    pub from: bool,
+    pub to: bool,
+}
+
+// Health seen in the previous collection, and the most recent transitions
+#[derive(Debug, Default)]
+pub struct HealthEvents {
+    allocators: HashMap<String, [(&'static str, bool); 3]>,
+    instances: HashMap<(String, String), bool>,
+    proxies: HashMap<String, bool>,
+    // When each allocator was first seen disconnected, if it still is
+    disconnected: HashMap<String, SystemTime>,
+    events: VecDeque<Event>,
+}
+
+impl HealthEvents {
+    // Compare a collection against the previous one, returning the transitions
+    // found. Nothing is reported for allocators or instances seen for the first time.
+    pub fn observe(&mut self, body: &AllocatorsRoot, label: AllocatorLabel) -> Vec<Event> {
+        let time = Utc::now().to_rfc3339();
+        let mut allocators = HashMap::new();
+        let mut instances = HashMap::new();
+        let mut disconnected = HashMap::new();
+        let mut found = Vec::new();
+
+        for zone in &body.zones {
+            for allocator in &zone.allocators {
+                let status = [
+                    ("connected", allocator.status.connected),
+                    ("healthy", allocator.status.healthy),
+                    ("maintenance", allocator.status.maintenance_mode),
+                ];
+                if let Some(previous) = self.allocators.get(&allocator.allocator_id) {
+                    for ((state, from), (_, to)) in previous.iter().zip(status) {
+                        if *from != to {
+                            found.push(Event {
+                                time: time.clone(),
+                                zone: zone.zone_id.clone(),
+                                allocator: Some(label.value(allocator).to_string()),
+                                proxy_id: None,
+                                cluster_id: None,
+                                instance_name: None,
+                                state,
+                                from: *from,
+                                to,
+                            });
+                        }
+                    }
+                }
+                allocators.insert(allocator.allocator_id.clone(), status);
+                if !allocator.status.connected {
+                    let since = self
+                        .disconnected
+                        .get(&allocator.allocator_id)
+                        .copied()
+                        .unwrap_or_else(SystemTime::now);
+                    disconnected.insert(allocator.allocator_id.clone(), since);
+                }
+
+                for instance in &allocator.instances {
+                    let key = (instance.cluster_id.clone(), instance.instance_name.clone());
+                    let healthy = instance.healthy.unwrap_or(false);
+                    if let Some(&from) = self.instances.get(&key) {
+                        if from != healthy {
+                            found.push(Event {
+                                time: time.clone(),
+                                zone: zone.zone_id.clone(),
+                                allocator: Some(label.value(allocator).to_string()),
+                                proxy_id: None,
+                                cluster_id: Some(instance.cluster_id.clone()),
+                                instance_name: Some(instance.instance_name.clone()),
+                                state: "healthy",
+                                from,
+                                to: healthy,
+                            });
+                        }
+                    }
+                    instances.insert(key, healthy);
+                }
+            }
+        }
+
+        self.allocators = allocators;
+        self.instances = instances;
+        self.disconnected = disconnected;
+        self.record(&found);
+        found
+    }
+
+    // Same as observe, for proxy health
+    pub fn observe_proxies(&mut self, body: &ProxiesRoot) -> Vec<Event> {
+        let time = Utc::now().to_rfc3339();
+        let mut found = Vec::new();
+        for proxy in &body.proxies {
+            if let Some(&from) = self.proxies.get(&proxy.proxy_id) {
+                if from != proxy.healthy {
+                    found.push(Event {
+                        time: time.clone(),
+                        zone: proxy.zone.clone(),
+                        allocator: None,
+                        proxy_id: Some(proxy.proxy_id.clone()),
+                        cluster_id: None,
+                        instance_name: None,
+                        state: "healthy",
+                        from,
+                        to: proxy.healthy,
+                    });
+                }
+            }
+        }
+        self.proxies = body
+            .proxies
+            .iter()
+            .map(|proxy| (proxy.proxy_id.clone(), proxy.healthy))
+            .collect();
+        self.record(&found);
+        found
+    }
+
+    fn record(&mut self, found: &[Event]) {
+        for event in found {
+            if self.events.len() == EVENT_BUFFER {
+                self.events.pop_front();
+            }
+            self.events.push_back(event.clone());
+        }
+    }
+
+    // How long an allocator has been disconnected, counted from the first
+    // collection that saw it so, or None while connected
+    pub fn disconnected_for(&self, allocator_id: &str) -> Option<Duration> {
+        self.disconnected
+            .get(allocator_id)
+            .map(|since| since.elapsed().unwrap_or_default())
+    }
+
+    // When each disconnected allocator was first seen so, in unix seconds
+    pub fn disconnected_since(&self) -> HashMap<String, u64> {
+        self.disconnected
+            .iter()
+            .map(|(id, since)| {
+                let since = since.duration_since(UNIX_EPOCH).unwrap_or_default();
+                (id.clone(), since.as_secs())
+            })
+            .collect()
+    }
+
+    // Carry disconnections over from a previous run. Allocators found
+    // connected by the next collection are forgotten.
+    pub fn restore_disconnected(&mut self, since: HashMap<String, u64>) {
+        self.disconnected = since
+            .into_iter()
+            .map(|(id, since)| (id, UNIX_EPOCH + Duration::from_secs(since)))
+            .collect();
+    }
+
+    // Recorded transitions, oldest first
+    pub fn recent(&self) -> Vec<Event> {
+        self.events.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::fixture;
+
+    #[test]
+    fn observe_reports_transitions_after_the_first_collection() {
+        let mut body: AllocatorsRoot = serde_json::from_str(fixture!("allocators")).unwrap();
+        let mut events = HealthEvents::default();
+        assert!(events.observe(&body, AllocatorLabel::Hostname).is_empty());
+
+        let allocator = &mut body.zones[0].allocators[0];
+        allocator.status.connected = false;
+        allocator.instances[0].healthy = Some(false);
+        let found = events.observe(&body, AllocatorLabel::Hostname);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].allocator.as_deref(), Some("alloc1.example.com"));
+        assert_eq!(
+            (found[0].state, found[0].from, found[0].to),
+            ("connected", true, false)
+        );
+        assert_eq!(
+            found[1].instance_name.as_deref(),
+            Some("instance-0000000000")
+        );
+        assert_eq!(events.recent().len(), 2);
+        assert!(events.disconnected_for("10.0.0.1").is_some());
+
+        assert!(events.observe(&body, AllocatorLabel::Hostname).is_empty());
+
+        body.zones[0].allocators[0].status.connected = true;
+        events.observe(&body, AllocatorLabel::Hostname);
+        assert!(events.disconnected_for("10.0.0.1").is_none());
+    }
+}
@@ -1,27 +1,102 @@
+use axum::http::header::{ACCEPT, CONTENT_TYPE};
+use axum::http::HeaderMap;
+use axum::response::{Html, Response};
 use axum::Extension;
 use axum::{extract::OriginalUri, http::StatusCode, response::IntoResponse, Json};
 use clap::{crate_description, crate_name, crate_version};
 use metrics_exporter_prometheus::PrometheusHandle;
 use serde_json::json;
 use serde_json::Value;
+use tower::timeout::error::Elapsed;
+use tower::BoxError;
 
+use crate::diagnostics;
 use crate::error::Error as RestError;
+use crate::metrics::{render_openmetrics, OPENMETRICS_CONTENT_TYPE};
+use crate::process;
+use crate::protobuf::{self, PROTOBUF_CONTENT_TYPE};
+use crate::state::{ALLOCATORS_PATH, PROXIES_PATH};
 use crate::State;
 
-// This is required in order to get the method from the request
-#[derive(Debug)]
-pub struct RequestMethod(pub hyper::Method);
+// Seconds a scraper turned away for overload is asked to wait
+const SCRAPE_RETRY_AFTER: u64 = 5;
 
 pub async fn metrics(
     Extension(recorder_handle): Extension<PrometheusHandle>,
     Extension(state): Extension<State>,
-) -> Result<String, RestError> {
+    headers: HeaderMap,
+) -> Result<Response, RestError> {
     log::info!("{{\"fn\": \"metrics\", \"method\":\"get\"}}");
-    match state.get_metrics().await {
-        Ok(_) => metrics::gauge!("ece_cluster_up", 1f64),
-        Err(_) => metrics::gauge!("ece_cluster_up", 0f64),
+    // Turn scrapes away rather than pile up collections and their memory
+    let _permit = match state.scrape_slots.try_acquire() {
+        Ok(permit) => permit,
+        Err(_) => {
+            metrics::increment_counter!("ece_scrapes_rejected_total");
+            log::warn!("{{\"msg\": \"too many concurrent scrapes, rejecting\"}}");
+            return Err(RestError::Overloaded(SCRAPE_RETRY_AFTER));
+        }
     };
-    Ok(recorder_handle.render())
+    state.scrape().await;
+    process::record();
+
+    // Native histograms need protobuf and exemplars need at least OpenMetrics,
+    // so prefer those formats when the scraper accepts them
+    let accept = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .unwrap_or_default();
+    if accept.contains("application/vnd.google.protobuf") {
+        let body = protobuf::render(&recorder_handle);
+        return Ok(([(CONTENT_TYPE, PROTOBUF_CONTENT_TYPE)], body).into_response());
+    }
+    match accept.contains("application/openmetrics-text") {
+        true => {
+            let body = render_openmetrics(&recorder_handle);
+            Ok(([(CONTENT_TYPE, OPENMETRICS_CONTENT_TYPE)], body).into_response())
+        }
+        false => Ok(recorder_handle.render().into_response()),
+    }
+}
+
+// Requests that ran past --server-timeout
+pub async fn handle_timeout(err: BoxError) -> RestError {
+    match err.is::<Elapsed>() {
+        true => {
+            log::warn!("{{\"msg\": \"request exceeded the server timeout\"}}");
+            RestError::GatewayTimeout("request exceeded the server timeout".to_string())
+        }
+        false => RestError::InternalServer(err.to_string()),
+    }
+}
+
+pub async fn debug_allocators(Extension(state): Extension<State>) -> impl IntoResponse {
+    log::info!("{{\"fn\": \"debug_allocators\", \"method\":\"get\"}}");
+    raw_response(&state, ALLOCATORS_PATH)
+}
+
+pub async fn debug_proxies(Extension(state): Extension<State>) -> impl IntoResponse {
+    log::info!("{{\"fn\": \"debug_proxies\", \"method\":\"get\"}}");
+    raw_response(&state, PROXIES_PATH)
+}
+
+// The ECE payload exactly as last fetched, before any parsing
+fn raw_response(state: &State, path: &str) -> impl IntoResponse {
+    match state.raw_responses.lock().unwrap().get(path).cloned() {
+        Some(raw) => Ok(([(CONTENT_TYPE, "application/json")], raw)),
+        None => Err((
+            StatusCode::NOT_FOUND,
+            "{\"error_code\": 404, \"message\": \"Not fetched from ECE yet\"}",
+        )),
+    }
+}
+
+pub async fn reload_data(Extension(state): Extension<State>) -> impl IntoResponse {
+    log::info!("{{\"fn\": \"reload_data\", \"method\":\"post\"}}");
+    state.refresh.notify_one();
+    (
+        StatusCode::ACCEPTED,
+        Json(json!({ "msg": "Refresh scheduled"})),
+    )
 }
 
 pub async fn health() -> Json<Value> {
@@ -29,11 +104,66 @@ pub async fn health() -> Json<Value> {
     Json(json!({ "msg": "Healthy"}))
 }
 
-pub async fn root() -> Json<Value> {
+// HTML landing page for browsers, the original JSON summary for everything else
+pub async fn root(headers: HeaderMap, Extension(state): Extension<State>) -> Response {
     log::info!("{{\"fn\": \"root\", \"method\":\"get\"}}");
-    Json(
-        json!({ "version": crate_version!(), "name": crate_name!(), "description": crate_description!()}),
-    )
+    let wants_html = headers
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.contains("text/html"))
+        .unwrap_or(false);
+    if !wants_html {
+        return Json(
+            json!({ "version": crate_version!(), "name": crate_name!(), "description": crate_description!()}),
+        )
+        .into_response();
+    }
+    Html(format!(
+        r#"<html>
+<head><title>{name}</title></head>
+<body>
+<h1>{name}</h1>
+<p>Version {version}, exporting from <code>{url}</code></p>
+<ul>
+<li><a href="/metrics">Metrics</a></li>
+<li><a href="/health">Health</a></li>
+<li><a href="/summary">Summary</a></li>
+<li><a href="/config">Config</a></li>
+</ul>
+</body>
+</html>
+"#,
+        name = crate_name!(),
+        version = crate_version!(),
+        url = escape_html(&state.url),
+    ))
+    .into_response()
+}
+
+pub async fn show_config(Extension(state): Extension<State>) -> Json<Value> {
+    log::info!("{{\"fn\": \"show_config\", \"method\":\"get\"}}");
+    Json(diagnostics::config(&state))
+}
+
+// Compact platform digest, refreshed by a collection like /metrics
+pub async fn summary(Extension(state): Extension<State>) -> Json<Value> {
+    log::info!("{{\"fn\": \"summary\", \"method\":\"get\"}}");
+    state.scrape().await;
+    Json(diagnostics::summary(&state))
+}
+
+// Recent health transitions, for building incident timelines
+pub async fn events(Extension(state): Extension<State>) -> Json<Value> {
+    log::info!("{{\"fn\": \"events\", \"method\":\"get\"}}");
+    Json(json!({ "events": state.health_events.lock().unwrap().recent() }))
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
 }
 
 pub async fn handler_404(OriginalUri(original_uri): OriginalUri) -> impl IntoResponse {
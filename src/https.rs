@@ -1,29 +1,47 @@
 use core::time::Duration;
-use hyper::client::HttpConnector;
-use hyper::Body;
+use http_body_util::combinators::BoxBody;
+use http_body_util::Full;
+use hyper::body::{Bytes, Incoming};
 use hyper::Request;
 use hyper::Response;
 use hyper_tls::HttpsConnector;
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::{Client, Error as ClientError};
+use hyper_util::rt::TokioExecutor;
 use native_tls::{Certificate, TlsConnector};
 use std::error::Error;
 
-//pub type Client = hyper::client::Client<HttpsConnector<HttpConnector>, Body>;
 type BoxResult<T> = std::result::Result<T, Box<dyn Error + Send + Sync>>;
 
+// Requests to ECE are small JSON payloads, sent in one piece
+pub type RequestBody = Full<Bytes>;
+
+// Responses stream in, whichever EceApi produced them
+pub type ResponseBody = BoxBody<Bytes, hyper::Error>;
+
 #[derive(Debug, Clone)]
-pub struct HttpsClient(hyper::client::Client<HttpsConnector<HttpConnector>, Body>);
+pub struct HttpsClient(Client<HttpsConnector<HttpConnector>, RequestBody>, Duration);
 
 impl HttpsClient {
     #[allow(dead_code)]
-    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
-        let Self(internal) = self;
+    pub async fn request(
+        &self,
+        req: Request<RequestBody>,
+    ) -> Result<Response<Incoming>, ClientError> {
+        let Self(internal, _) = self;
         internal.request(req).await
     }
+    // How long callers should wait for a response once connected
+    pub fn request_timeout(&self) -> Duration {
+        let Self(_, timeout) = self;
+        *timeout
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ClientConfig<'a> {
-    timeout: u64,
+    connect_timeout: u64,
+    request_timeout: u64,
     set_nodelay: bool,
     enforce_http: bool,
     set_reuse_address: bool,
@@ -40,7 +58,8 @@ pub struct ClientBuilder<'a> {
 impl Default for ClientConfig<'_> {
     fn default() -> Self {
         ClientConfig {
-            timeout: 60u64,
+            connect_timeout: 60u64,
+            request_timeout: 60u64,
             set_nodelay: false,
             enforce_http: false,
             set_reuse_address: false,
@@ -64,7 +83,18 @@ impl<'a> ClientBuilder<'a> {
     }
     #[allow(dead_code)]
     pub fn timeout(mut self, arg: u64) -> Self {
-        self.config.timeout = arg;
+        self.config.connect_timeout = arg;
+        self.config.request_timeout = arg;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn connect_timeout(mut self, arg: u64) -> Self {
+        self.config.connect_timeout = arg;
+        self
+    }
+    #[allow(dead_code)]
+    pub fn request_timeout(mut self, arg: u64) -> Self {
+        self.config.request_timeout = arg;
         self
     }
     #[allow(dead_code)]
@@ -116,20 +146,22 @@ impl<'a> ClientBuilder<'a> {
                 .build()?,
         };
 
-        let mut http = hyper::client::HttpConnector::new();
+        let mut http = HttpConnector::new();
 
-        // Create timeout Duration
-        let timeout = Duration::new(self.config.timeout, 0);
+        // Create timeout Durations
+        let connect_timeout = Duration::new(self.config.connect_timeout, 0);
+        let request_timeout = Duration::new(self.config.request_timeout, 0);
 
-        http.set_connect_timeout(Some(timeout));
+        http.set_connect_timeout(Some(connect_timeout));
         http.set_nodelay(self.config.set_nodelay);
         http.enforce_http(self.config.enforce_http);
         http.set_reuse_address(self.config.set_reuse_address);
 
-        let https: hyper_tls::HttpsConnector<hyper::client::HttpConnector> =
-            hyper_tls::HttpsConnector::from((http, tls_connector.into()));
+        let https: HttpsConnector<HttpConnector> =
+            HttpsConnector::from((http, tls_connector.into()));
         Ok(HttpsClient(
-            hyper::Client::builder().build::<_, hyper::Body>(https),
+            Client::builder(TokioExecutor::new()).build(https),
+            request_timeout,
         ))
     }
 }
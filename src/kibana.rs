@@ -0,0 +1,33 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::elasticsearch::{PlanInfo, Topology};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KibanaClustersRoot {
+    pub kibana_clusters: Vec<KibanaCluster>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KibanaCluster {
+    pub cluster_id: String,
+    pub cluster_name: String,
+    pub deployment_id: Option<String>,
+    pub healthy: bool,
+    pub status: String,
+    pub elasticsearch_cluster: Option<ElasticsearchReference>,
+    pub topology: Option<Topology>,
+    pub plan_info: Option<PlanInfo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ElasticsearchReference {
+    pub elasticsearch_id: String,
+}
+
+impl KibanaCluster {
+    // The current plan, when one has been applied
+    pub fn plan(&self) -> Option<&Value> {
+        self.plan_info.as_ref()?.current.as_ref()?.plan.as_ref()
+    }
+}
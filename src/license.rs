@@ -0,0 +1,9 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct License {
+    pub expiry_date: DateTime<Utc>,
+    #[serde(rename = "type")]
+    pub license_type: Option<String>,
+}
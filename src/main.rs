@@ -1,27 +1,76 @@
-use axum::{extract::Extension, handler::Handler, middleware, routing::get, Router};
+use axum::{
+    error_handling::HandleErrorLayer,
+    extract::Extension,
+    middleware,
+    routing::{get, post},
+    Router,
+};
 use chrono::Local;
 use clap::{crate_name, crate_version, Arg, Command};
 use env_logger::{Builder, Target};
 use log::LevelFilter;
+use socket2::{Domain, Protocol, Socket, TcpKeepalive, Type};
 use std::io::Write;
 use std::net::SocketAddr;
+use std::time::Duration;
+use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
 
 mod allocator;
+mod api;
+mod auth;
+mod availability;
+#[cfg(feature = "aws")]
+mod aws;
+mod body;
+mod collector;
+mod compat;
+mod config;
+#[cfg(feature = "cost")]
+mod cost;
+mod credentials;
+mod custom;
+mod deployment;
+mod diagnostics;
+mod drop_labels;
+mod elasticsearch;
 mod error;
+mod events;
 mod handlers;
 mod https;
+mod kibana;
+#[cfg(feature = "push")]
+mod license;
 mod metrics;
+#[cfg(test)]
+mod mock;
+mod names;
+mod native;
+mod process;
+mod protobuf;
 mod proxy;
+mod secret_file;
+mod session;
+mod snapshot;
 mod state;
+mod template;
+#[cfg(feature = "push")]
+mod textfile;
+mod vault;
+#[cfg(feature = "push")]
+mod webhook;
 
 use crate::metrics::{setup_metrics_recorder, track_metrics};
-use handlers::{handler_404, health, metrics, root};
+use config::Settings;
+use handlers::{
+    debug_allocators, debug_proxies, events, handle_timeout, handler_404, health, metrics,
+    reload_data, root, show_config, summary,
+};
 use state::State;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let opts = Command::new(crate_name!())
+// Command line and environment options, also used to build settings in tests
+fn cli() -> Command<'static> {
+    Command::new(crate_name!())
         .version(crate_version!())
         .author("")
         .about(crate_name!())
@@ -34,13 +83,35 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .default_value("8080")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("listen_backlog")
+                .long("listen-backlog")
+                .help("Connections the listening socket queues before refusing more")
+                .env("ECE_LISTEN_BACKLOG")
+                .default_value("1024")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tcp_nodelay")
+                .long("tcp-nodelay")
+                .help("Disable Nagle's algorithm on connections to the exporter")
+                .env("ECE_TCP_NODELAY")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("tcp_keepalive")
+                .long("tcp-keepalive")
+                .help("Send TCP keep-alive probes after a connection to the exporter is idle this many seconds")
+                .env("ECE_TCP_KEEPALIVE")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("username")
                 .short('u')
                 .long("username")
                 .help("ECE Username")
                 .env("ECE_USERNAME")
-                .required_unless_present("apikey")
+                .required_unless_present_any(["apikey", "apikey_file", "apikey_from", "vault_addr"])
                 .takes_value(true),
         )
         .arg(
@@ -49,7 +120,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .long("password")
                 .help("ECE Password")
                 .env("ECE_PASSWORD")
-                .required_unless_present("apikey")
+                .required_unless_present_any([
+                    "apikey",
+                    "apikey_file",
+                    "apikey_from",
+                    "password_file",
+                    "vault_addr",
+                ])
                 .takes_value(true),
         )
         .arg(
@@ -62,6 +139,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .conflicts_with("username")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("apikey_file")
+                .long("apikey-file")
+                .help("File containing the ECE API Key, reloaded when it changes")
+                .env("ECE_APIKEY_FILE")
+                .conflicts_with_all(&["apikey", "username"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("apikey_from")
+                .long("apikey-from")
+                .help("Load the ECE API Key at startup from aws-sm://name[#key] or aws-ssm://name")
+                .env("ECE_APIKEY_FROM")
+                .conflicts_with_all(&["apikey", "apikey_file", "vault_addr", "username"])
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("session_auth")
+                .long("session-auth")
+                .help("Log in once with the username and password and reuse the session token")
+                .env("ECE_SESSION_AUTH")
+                .requires("username")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("password_file")
+                .long("password-file")
+                .help("File containing the ECE Password, reloaded when it changes")
+                .env("ECE_PASSWORD_FILE")
+                .conflicts_with("password")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("url")
                 .short('U')
@@ -80,6 +189,42 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .env("ECE_TIMEOUT")
                 .takes_value(true),
         )
+        .arg(
+            Arg::new("compat")
+                .long("compat")
+                .help("Scheme renaming metrics and labels to match other dashboards: ecs, or a YAML scheme file")
+                .env("ECE_COMPAT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("connect_timeout")
+                .long("connect-timeout")
+                .help("Timeout for connecting to ECE, defaults to --timeout")
+                .env("ECE_CONNECT_TIMEOUT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("request_timeout")
+                .long("request-timeout")
+                .help("Timeout for ECE to respond once connected, defaults to --timeout")
+                .env("ECE_REQUEST_TIMEOUT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("probe_timeout")
+                .long("probe-timeout")
+                .help("Timeout for each deployment endpoint probe of the endpoints collector")
+                .default_value("5")
+                .env("ECE_PROBE_TIMEOUT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("server_timeout")
+                .long("server-timeout")
+                .help("Time allowed to answer a request to the exporter, defaults to --timeout")
+                .env("ECE_SERVER_TIMEOUT")
+                .takes_value(true),
+        )
         .arg(
             Arg::new("eru_cost")
                 .short('e')
@@ -89,7 +234,304 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 .env("ECE_ERU_COST")
                 .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::new("drop_label")
+                .long("drop-label")
+                .help("Remove this label from every series, merging series left identical, may be repeated")
+                .env("ECE_DROP_LABEL")
+                .multiple_occurrences(true)
+                .value_delimiter(',')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("eru_size_gb")
+                .long("eru-size-gb")
+                .help("GB of RAM covered by one ERU in cost metrics")
+                .default_value("64")
+                .env("ECE_ERU_SIZE_GB")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("eru_size_gb_override")
+                .long("eru-size-gb-override")
+                .help("GB of RAM per ERU for one instance configuration, as configuration_id=GB, may be repeated")
+                .env("ECE_ERU_SIZE_GB_OVERRIDE")
+                .multiple_occurrences(true)
+                .value_delimiter(',')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cost_unit")
+                .long("cost-unit")
+                .help("Unit of the monthly cost gauge: cents or dollars")
+                .default_value("cents")
+                .env("ECE_COST_UNIT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("cost_precision")
+                .long("cost-precision")
+                .help("Round the monthly cost gauge to this many decimal places")
+                .env("ECE_COST_PRECISION")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("collectors")
+                .short('c')
+                .long("collectors")
+                .help(
+                    "Comma separated list of collectors to run: allocators, proxies, elasticsearch, kibana, deployments, remote_clusters, endpoints, cluster_health, shard_allocation, jvm_heap, indices, slm, ilm",
+                )
+                .default_value("allocators,proxies")
+                .env("ECE_COLLECTORS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("custom_metrics")
+                .long("custom-metrics")
+                .help("YAML file of extra collectors, each turning an ECE API path into gauges through selectors")
+                .env("ECE_CUSTOM_METRICS")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("user_agent")
+                .long("user-agent")
+                .help("User-Agent sent on ECE requests")
+                .default_value(concat!(crate_name!(), "/", crate_version!()))
+                .env("ECE_USER_AGENT")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("header")
+                .short('H')
+                .long("header")
+                .help("Extra 'Name: value' header sent on every ECE request, may be repeated")
+                .env("ECE_HEADERS")
+                .multiple_occurrences(true)
+                .value_delimiter('\n')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_concurrent_requests")
+                .long("max-concurrent-requests")
+                .help("Maximum number of simultaneous requests to the ECE API")
+                .env("ECE_MAX_CONCURRENT_REQUESTS")
+                .default_value("8")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_concurrent_scrapes")
+                .long("max-concurrent-scrapes")
+                .help("Maximum number of simultaneous /metrics renders, beyond which scrapes get a 503")
+                .env("ECE_MAX_CONCURRENT_SCRAPES")
+                .default_value("4")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("max_response_bytes")
+                .long("max-response-bytes")
+                .help("Maximum size of an ECE API response body")
+                .default_value("104857600")
+                .env("ECE_MAX_RESPONSE_BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("aggregate_only")
+                .long("aggregate-only")
+                .help("Drop per-instance series, exporting only zone, allocator, and configuration rollups")
+                .env("ECE_AGGREGATE_ONLY")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("allocator_label")
+                .long("allocator-label")
+                .help("Allocator identity used for the ip label: hostname, host-ip, or allocator-id")
+                .env("ECE_ALLOCATOR_LABEL")
+                .default_value("hostname")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("deployment_query")
+                .long("deployment-query")
+                .help("JSON deployments _search body limiting which deployments the deployment collectors cover")
+                .env("ECE_DEPLOYMENT_QUERY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("disable_mb_metrics")
+                .long("disable-mb-metrics")
+                .help("Only export memory in bytes, dropping the legacy MB metrics")
+                .env("ECE_DISABLE_MB_METRICS")
+                .takes_value(false),
+        )
+        .arg(
+            Arg::new("name_cache_ttl")
+                .long("name-cache-ttl")
+                .help("Seconds to cache deployment and instance configuration names used to enrich labels")
+                .default_value("300")
+                .env("ECE_NAME_CACHE_TTL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("webhook_url")
+                .long("webhook-url")
+                .help("POST a JSON payload here when an allocator disconnects, a proxy turns unhealthy, or the license nears expiry")
+                .env("ECE_WEBHOOK_URL")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("license_expiry_warning")
+                .long("license-expiry-warning")
+                .help("Days before the ECE license expires to notify the webhook")
+                .default_value("30")
+                .env("ECE_LICENSE_EXPIRY_WARNING")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("vault_addr")
+                .long("vault-addr")
+                .help("Vault address to read the ECE API key from")
+                .env("VAULT_ADDR")
+                .conflicts_with_all(&["apikey", "apikey_file", "username"])
+                .requires("vault_path")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("vault_path")
+                .long("vault-path")
+                .help("Vault KV path holding the ECE API key, e.g. secret/data/ece")
+                .env("ECE_VAULT_PATH")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("vault_token")
+                .long("vault-token")
+                .help("Vault token")
+                .env("VAULT_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("vault_key")
+                .long("vault-key")
+                .help("Key within the Vault secret holding the ECE API key")
+                .default_value("apikey")
+                .env("ECE_VAULT_KEY")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("vault_refresh_interval")
+                .long("vault-refresh-interval")
+                .help("Seconds between re-reading the ECE API key from Vault")
+                .default_value("300")
+                .env("ECE_VAULT_REFRESH_INTERVAL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("snapshot_file")
+                .long("snapshot-file")
+                .help("Persist the last good ECE responses here and serve them after a restart until ECE is reachable")
+                .env("ECE_SNAPSHOT_FILE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("stale_while_revalidate")
+                .long("stale-while-revalidate")
+                .help("Seconds to keep serving the last good data while a refresh is in flight or failing, 0 to always wait")
+                .default_value("0")
+                .env("ECE_STALE_WHILE_REVALIDATE")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("instance_label")
+                .long("instance-label")
+                .help("Extra label on instance series rendered from a template, as label={{tag:key}}-{{zone}}, may be repeated")
+                .env("ECE_INSTANCE_LABEL")
+                .multiple_occurrences(true)
+                .value_delimiter('\n')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("tag_as_metric")
+                .long("tag-as-metric")
+                .help("Export a numeric allocator tag as a gauge instead of a label, as tag=metric_name, may be repeated")
+                .env("ECE_TAG_AS_METRIC")
+                .multiple_occurrences(true)
+                .value_delimiter(',')
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("textfile_output")
+                .long("textfile-output")
+                .help("Write metrics to this file every refresh interval, for node_exporter's textfile collector")
+                .env("ECE_TEXTFILE_OUTPUT")
+                .conflicts_with("once")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("refresh_interval")
+                .long("refresh-interval")
+                .help("Seconds between background collections for --textfile-output")
+                .default_value("60")
+                .env("ECE_REFRESH_INTERVAL")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("debug_token")
+                .long("debug-token")
+                .help("Enable /debug/allocators and /debug/proxies, requiring this bearer token")
+                .env("ECE_DEBUG_TOKEN")
+                .hide_env_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("refresh_jitter")
+                .long("refresh-jitter")
+                .help("Maximum random delay in seconds added before each background collection")
+                .default_value("0")
+                .env("ECE_REFRESH_JITTER")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("auth_failure_cooldown")
+                .long("auth-failure-cooldown")
+                .help("Seconds to stop calling ECE after it rejects the credentials")
+                .default_value("300")
+                .env("ECE_AUTH_FAILURE_COOLDOWN")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::new("once")
+                .long("once")
+                .help("Collect metrics once, print them to stdout, and exit")
+                .env("ECE_ONCE")
+                .takes_value(false),
+        )
+        .subcommand(
+            Command::new("check-config")
+                .about("Validate the supplied configuration and exit without contacting ECE"),
+        )
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let opts = cli().get_matches();
+
+    // Validate configuration, exiting with every problem found
+    let settings = match Settings::from_opts(&opts) {
+        Ok(settings) => settings,
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("error: {}", error);
+            }
+            std::process::exit(1);
+        }
+    };
+    if opts.subcommand_matches("check-config").is_some() {
+        println!("configuration ok");
+        std::process::exit(0);
+    }
 
     // Initialize log Builder
     Builder::new()
@@ -102,47 +544,125 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 record.args()
             )
         })
-        .target(Target::Stdout)
+        .target(match settings.once {
+            // Keep stdout clean for the metrics output
+            true => Target::Stderr,
+            false => Target::Stdout,
+        })
         .filter_level(LevelFilter::Info)
         .parse_default_env()
         .init();
 
-    // Set port
-    let port: u16 = opts.value_of("port").unwrap().parse().unwrap_or_else(|_| {
-        eprintln!("specified port isn't in a valid range, setting to 8080");
-        8080
-    });
-
     // Create state for axum
-    let state = State::new(opts.clone()).await?;
+    let state = State::new(&settings).await?;
 
-    // Create prometheus handle
-    let recorder_handle = setup_metrics_recorder();
+    // Create prometheus handle, keeping metrics alive between background refreshes
+    // and, when serving stale data, for as long as it may be served
+    let idle_timeout = match settings.textfile_output {
+        Some(_) => {
+            ((settings.refresh_interval + settings.refresh_jitter) * 2).max(Duration::from_secs(10))
+        }
+        None => Duration::from_secs(10),
+    }
+    .max(settings.stale_while_revalidate);
+    let recorder_handle = setup_metrics_recorder(
+        idle_timeout,
+        settings.compat.clone(),
+        settings.drop_labels.clone(),
+    );
+
+    // Perform a single collection and exit if requested
+    if settings.once {
+        let success = state.collect().await;
+        process::record();
+        print!("{}", recorder_handle.render());
+        std::process::exit(if success { 0 } else { 1 });
+    }
+
+    // Dump a state summary to the log on SIGUSR1
+    diagnostics::spawn_signal_handler(state.clone());
+
+    // Periodically write metrics for node_exporter's textfile collector
+    match &settings.textfile_output {
+        #[cfg(feature = "push")]
+        Some(path) => textfile::spawn(
+            state.clone(),
+            recorder_handle.clone(),
+            path.to_string(),
+            settings.refresh_interval,
+            settings.refresh_jitter,
+        ),
+        // The config rejects --textfile-output in builds without the push feature
+        #[cfg(not(feature = "push"))]
+        Some(_) => unreachable!("--textfile-output requires the push feature"),
+        None => state.spawn_refresh_listener(),
+    }
 
     // These should be authenticated
     let base = Router::new().route("/", get(root));
 
+    // Raw ECE payloads, only served when a debug token is configured
+    let debug = match settings.debug_token.is_some() {
+        true => Router::new()
+            .route("/debug/allocators", get(debug_allocators))
+            .route("/debug/proxies", get(debug_proxies))
+            .route_layer(middleware::from_fn(auth::require_token)),
+        false => Router::new(),
+    };
+
+    // Effective config, and forcing a collection outside the normal interval,
+    // guarded by the debug token if set
+    let lifecycle = Router::new()
+        .route("/config", get(show_config))
+        .route("/events", get(events))
+        .route("/-/reload-data", post(reload_data))
+        .route_layer(middleware::from_fn(auth::require_token));
+
     // These should NOT be authenticated
     let standard = Router::new()
         .route("/health", get(health))
-        .route("/metrics", get(metrics));
+        .route("/metrics", get(metrics))
+        .route("/summary", get(summary));
 
     let app = Router::new()
         .merge(base)
+        .merge(debug)
+        .merge(lifecycle)
         .merge(standard)
+        // add a fallback service for handling routes to unknown paths
+        .fallback(handler_404)
+        // Answer with a 504 rather than hold the connection open on a hung ECE call
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(handle_timeout))
+                .timeout(state.server_timeout),
+        )
         .layer(TraceLayer::new_for_http())
-        .route_layer(middleware::from_fn(track_metrics))
+        // Layered after the fallback so unknown paths are counted too
+        .layer(middleware::from_fn(track_metrics))
         .layer(Extension(recorder_handle))
         .layer(Extension(state));
 
-    // add a fallback service for handling routes to unknown paths
-    let app = app.fallback(handler_404.into_service());
-
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let addr = SocketAddr::from(([0, 0, 0, 0], settings.port));
     log::info!("Listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+    let listener = listen(addr, &settings)?;
+    axum::serve(listener, app)
+        .tcp_nodelay(settings.tcp_nodelay)
         .await?;
 
     Ok(())
 }
+
+// Bind the listening socket with the configured backlog. Keep-alive set here
+// is inherited by every accepted connection.
+fn listen(addr: SocketAddr, settings: &Settings) -> std::io::Result<tokio::net::TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    socket.set_reuse_address(true)?;
+    if let Some(idle) = settings.tcp_keepalive {
+        socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(settings.listen_backlog)?;
+    socket.set_nonblocking(true)?;
+    tokio::net::TcpListener::from_std(socket.into())
+}
@@ -1,31 +1,125 @@
-use axum::{http::Request, middleware::Next, response::IntoResponse};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::IntoResponse,
+};
 use core::time::Duration;
+use metrics::Recorder;
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use metrics_util::MetricKindMask;
-use std::time::Instant;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-pub fn setup_metrics_recorder() -> PrometheusHandle {
-    const EXPONENTIAL_SECONDS: &[f64] = &[
-        0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+use crate::compat::{CompatRecorder, Scheme};
+use crate::drop_labels::DropLabelsRecorder;
+use crate::native;
+
+const EXPONENTIAL_SECONDS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+// Most recent traced request per http_requests_duration_seconds bucket,
+// keyed by the rendered labels and the bucket's upper bound
+#[derive(Clone)]
+pub struct Exemplar {
+    pub trace_id: String,
+    pub value: f64,
+    pub timestamp: f64,
+}
+
+// The --drop-label and --compat rules, for series tracked outside the recorder
+struct Rewrite {
+    compat: Option<Scheme>,
+    drop_labels: Vec<String>,
+}
+
+static REWRITE: OnceLock<Rewrite> = OnceLock::new();
+
+// Name and labels a series is rendered with once the recorder has dropped and
+// renamed its labels, so native histograms and exemplars match the output
+pub fn rendered(name: &str, labels: &[(&str, String)]) -> (String, Vec<(String, String)>) {
+    let rewrite = REWRITE.get();
+    let labels = labels
+        .iter()
+        .filter(|(key, _)| !rewrite.is_some_and(|r| r.drop_labels.iter().any(|d| d == key)))
+        .map(
+            |(key, value)| match rewrite.and_then(|r| r.compat.as_ref()) {
+                Some(scheme) => (scheme.label(key), value.clone()),
+                None => (key.to_string(), value.clone()),
+            },
+        )
+        .collect();
+    let name = match rewrite.and_then(|r| r.compat.as_ref()) {
+        Some(scheme) => scheme.metric(name),
+        None => name.to_string(),
+    };
+    (name, labels)
+}
+
+fn exemplars() -> &'static Mutex<HashMap<(String, String), Exemplar>> {
+    static EXEMPLARS: OnceLock<Mutex<HashMap<(String, String), Exemplar>>> = OnceLock::new();
+    EXEMPLARS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Metrics not updated within idle_timeout are dropped from the output
+pub fn setup_metrics_recorder(
+    idle_timeout: Duration,
+    compat: Option<Scheme>,
+    drop_labels: Vec<String>,
+) -> PrometheusHandle {
+    // ECE admin API calls can take much longer than requests to the exporter
+    const API_SECONDS: &[f64] = &[
+        0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
     ];
 
-    PrometheusBuilder::new()
+    let recorder = PrometheusBuilder::new()
         .idle_timeout(
             MetricKindMask::COUNTER | MetricKindMask::GAUGE,
-            Some(Duration::from_secs(10)),
+            Some(idle_timeout),
         )
         .set_buckets_for_metric(
             Matcher::Full("http_requests_duration_seconds".to_string()),
             EXPONENTIAL_SECONDS,
         )
         .unwrap()
-        .install_recorder()
+        .set_buckets_for_metric(
+            Matcher::Full("ece_api_request_duration_seconds".to_string()),
+            API_SECONDS,
+        )
         .unwrap()
+        .build_recorder();
+    let handle = recorder.handle();
+    let _ = REWRITE.set(Rewrite {
+        compat: compat.clone(),
+        drop_labels: drop_labels.clone(),
+    });
+
+    let recorder: Box<dyn Recorder> = match compat {
+        Some(scheme) => Box::new(CompatRecorder::new(recorder, scheme)),
+        None => Box::new(recorder),
+    };
+    // Labels are dropped by their own names, before any compat rename
+    let recorder: Box<dyn Recorder> = match drop_labels.is_empty() {
+        true => recorder,
+        false => Box::new(DropLabelsRecorder::new(recorder, drop_labels, idle_timeout)),
+    };
+    metrics::set_boxed_recorder(recorder).unwrap();
+    handle
 }
 
-pub async fn track_metrics<B>(req: Request<B>, next: Next<B>) -> impl IntoResponse {
+pub async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
     let start = Instant::now();
-    let path = req.uri().path().to_owned();
+    let trace_id = trace_id(req.headers());
+    // Use the matched route rather than the raw uri to bound label cardinality
+    let path = match req.extensions().get::<MatchedPath>() {
+        Some(matched) => matched.as_str().to_owned(),
+        None => "/<unmatched>".to_owned(),
+    };
     let method = req.method().clone();
     let response = next.run(req).await;
     let latency = start.elapsed().as_secs_f64();
@@ -39,6 +133,89 @@ pub async fn track_metrics<B>(req: Request<B>, next: Next<B>) -> impl IntoRespon
 
     metrics::increment_counter!("http_requests_total", &labels);
     metrics::histogram!("http_requests_duration_seconds", latency, &labels);
+    native::observe("http_requests_duration_seconds", &labels, latency);
+    if let Some(trace_id) = trace_id {
+        record_exemplar(&labels, latency, trace_id);
+    }
 
     response
 }
+
+// Trace id from a W3C traceparent header, e.g. 00-<trace id>-<parent id>-01
+fn trace_id(headers: &HeaderMap) -> Option<String> {
+    let traceparent = headers.get("traceparent")?.to_str().ok()?;
+    let trace_id = traceparent.split('-').nth(1)?;
+    let valid = trace_id.len() == 32
+        && trace_id.chars().all(|c| c.is_ascii_hexdigit())
+        && trace_id.chars().any(|c| c != '0');
+    valid.then(|| trace_id.to_ascii_lowercase())
+}
+
+fn record_exemplar(labels: &[(&str, String)], latency: f64, trace_id: String) {
+    // Cumulative buckets, so the exemplar belongs to the first bound it fits under
+    let le = match EXPONENTIAL_SECONDS.iter().find(|bound| latency <= **bound) {
+        Some(bound) => bound.to_string(),
+        None => "+Inf".to_string(),
+    };
+    let (_, labels) = rendered("http_requests_duration_seconds", labels);
+    let labels = exemplar_labels(labels.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or_default();
+    let exemplar = Exemplar {
+        trace_id,
+        value: latency,
+        timestamp,
+    };
+    exemplars().lock().unwrap().insert((labels, le), exemplar);
+}
+
+// Exemplars are keyed by labels as rendered in the text format
+fn exemplar_labels<'a>(labels: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    labels
+        .map(|(key, value)| format!("{}=\"{}\"", key, value))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Exemplar recorded for a http_requests_duration_seconds bucket, if any
+pub fn exemplar(labels: &[(String, String)], le: &str) -> Option<Exemplar> {
+    let labels = exemplar_labels(labels.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    exemplars()
+        .lock()
+        .unwrap()
+        .get(&(labels, le.to_string()))
+        .cloned()
+}
+
+// Render in the OpenMetrics format, attaching the recorded trace exemplars to
+// the matching http_requests_duration_seconds buckets
+pub fn render_openmetrics(handle: &PrometheusHandle) -> String {
+    let bucket = format!(
+        "{}_bucket{{",
+        rendered("http_requests_duration_seconds", &[]).0
+    );
+    let exemplars = exemplars().lock().unwrap().clone();
+    let text = handle.render();
+    let mut output = String::with_capacity(text.len() + 6);
+    for line in text.lines() {
+        output.push_str(line);
+        if let Some(exemplar) = line
+            .strip_prefix(bucket.as_str())
+            .and_then(|rest| rest.split_once("} "))
+            .and_then(|(labels, _)| labels.rsplit_once(",le=\""))
+            .and_then(|(labels, le)| {
+                exemplars.get(&(labels.to_string(), le.trim_end_matches('"').to_string()))
+            })
+        {
+            output.push_str(&format!(
+                " # {{trace_id=\"{}\"}} {} {}",
+                exemplar.trace_id, exemplar.value, exemplar.timestamp
+            ));
+        }
+        output.push('\n');
+    }
+    output.push_str("# EOF\n");
+    output
+}
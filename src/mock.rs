@@ -0,0 +1,141 @@
+use core::time::Duration;
+use futures_util::future::BoxFuture;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Method, Request, Response, StatusCode};
+use metrics_util::debugging::{DebugValue, DebuggingRecorder, Snapshotter};
+use metrics_util::MetricKind;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::api::EceApi;
+use crate::collector::Collection;
+use crate::config::Settings;
+use crate::error::Error as RestError;
+use crate::https::{RequestBody, ResponseBody};
+use crate::state::State;
+
+// Recorded ECE response, as found under tests/fixtures
+macro_rules! fixture {
+    ($name:literal) => {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/",
+            $name,
+            ".json"
+        ))
+    };
+}
+pub(crate) use fixture;
+
+const NOT_FOUND: &str = r#"{"errors": [{"code": "root.not_found", "message": "no fixture"}]}"#;
+
+// EceApi answering from fixtures keyed by API path, e.g. api/v1/platform,
+// and remembering every request it was sent
+#[derive(Debug, Default)]
+pub struct MockApi {
+    responses: HashMap<String, (StatusCode, &'static str)>,
+    requests: Mutex<Vec<(Method, String)>>,
+}
+
+impl MockApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond(mut self, path: &str, status: StatusCode, body: &'static str) -> Self {
+        self.responses.insert(path.to_string(), (status, body));
+        self
+    }
+
+    pub fn requests(&self) -> Vec<(Method, String)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl EceApi for MockApi {
+    fn send(
+        &self,
+        req: Request<RequestBody>,
+    ) -> BoxFuture<'_, Result<Response<ResponseBody>, RestError>> {
+        let path = req.uri().path().trim_start_matches('/').to_string();
+        let (status, body) = self
+            .responses
+            .get(&path)
+            .cloned()
+            .unwrap_or((StatusCode::NOT_FOUND, NOT_FOUND));
+        self.requests
+            .lock()
+            .unwrap()
+            .push((req.method().clone(), path));
+        let body = Full::new(Bytes::from_static(body.as_bytes()))
+            .map_err(|never| match never {})
+            .boxed();
+        let response = Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body)
+            .expect("response builder");
+        Box::pin(async move { Ok(response) })
+    }
+
+    fn request_timeout(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+}
+
+// Every test shares one recorder, so each asserts on series only its fixtures produce
+pub fn snapshotter() -> &'static Snapshotter {
+    static SNAPSHOTTER: OnceLock<Snapshotter> = OnceLock::new();
+    SNAPSHOTTER.get_or_init(|| {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        recorder.install().expect("recorder already installed");
+        snapshotter
+    })
+}
+
+// Current value of the gauge with this name carrying at least these labels
+pub fn gauge(name: &str, labels: &[(&str, &str)]) -> Option<f64> {
+    snapshotter()
+        .snapshot()
+        .into_vec()
+        .into_iter()
+        .find_map(|(key, _, _, value)| {
+            let (kind, key) = key.into_parts();
+            let matches = kind == MetricKind::Gauge
+                && key.name() == name
+                && labels
+                    .iter()
+                    .all(|(k, v)| key.labels().any(|l| l.key() == *k && l.value() == *v));
+            match (matches, value) {
+                (true, DebugValue::Gauge(value)) => Some(value.into_inner()),
+                _ => None,
+            }
+        })
+}
+
+pub async fn state(api: Arc<MockApi>, args: &[&str]) -> State {
+    snapshotter();
+    let mut argv = vec![
+        "exporter",
+        "--url",
+        "https://ece.test:12443",
+        "--apikey",
+        "key",
+    ];
+    argv.extend_from_slice(args);
+    let settings = Settings::from_opts(&crate::cli().get_matches_from(argv)).unwrap();
+    State::with_api(&settings, api).await.unwrap()
+}
+
+// Run one registered collector against the mock
+pub async fn collect(state: &State, collector: &str) -> Result<(), RestError> {
+    state
+        .registry
+        .get(collector)
+        .unwrap()
+        .collect(state, &Collection::default())
+        .await
+}
@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::deployment::DeploymentsListRoot;
+
+// Entry of api/v1/platform/configuration/instances
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InstanceConfiguration {
+    pub id: String,
+    pub name: String,
+}
+
+// Human readable names for the ids found in ECE payloads, shared by the
+// collectors to enrich their labels
+#[derive(Debug, Default, Clone)]
+pub struct Names {
+    deployments: HashMap<String, String>,
+    // Deployment id owning each cluster, i.e. each deployment resource
+    clusters: HashMap<String, String>,
+    configurations: HashMap<String, String>,
+}
+
+impl Names {
+    pub fn set_deployments(&mut self, body: &DeploymentsListRoot) {
+        self.deployments = body
+            .deployments
+            .iter()
+            .map(|d| (d.id.clone(), d.name.clone()))
+            .collect();
+        self.clusters = body
+            .deployments
+            .iter()
+            .flat_map(|d| {
+                d.resources
+                    .iter()
+                    .map(move |r| (r.id.clone(), d.id.clone()))
+            })
+            .collect();
+    }
+
+    pub fn set_configurations(&mut self, configurations: &[InstanceConfiguration]) {
+        self.configurations = configurations
+            .iter()
+            .map(|c| (c.id.clone(), c.name.clone()))
+            .collect();
+    }
+
+    pub fn deployment(&self, deployment_id: &str) -> Option<&str> {
+        self.deployments.get(deployment_id).map(String::as_str)
+    }
+
+    // Name of the deployment a cluster belongs to, for payloads that only
+    // carry the cluster id
+    pub fn cluster_deployment(&self, cluster_id: &str) -> Option<&str> {
+        self.clusters
+            .get(cluster_id)
+            .and_then(|id| self.deployment(id))
+    }
+
+    // Name of the deployment behind a cluster payload, by its deployment id
+    // when present
+    pub fn deployment_of(&self, deployment_id: Option<&str>, cluster_id: &str) -> &str {
+        deployment_id
+            .and_then(|id| self.deployment(id))
+            .or_else(|| self.cluster_deployment(cluster_id))
+            .unwrap_or("null")
+    }
+
+    // Rough footprint of the cached names: the length of every id and name
+    pub fn bytes(&self) -> usize {
+        [&self.deployments, &self.clusters, &self.configurations]
+            .iter()
+            .flat_map(|map| map.iter())
+            .map(|(id, name)| id.len() + name.len())
+            .sum()
+    }
+
+    pub fn configuration(&self, configuration_id: &str) -> Option<&str> {
+        self.configurations
+            .get(configuration_id)
+            .map(String::as_str)
+    }
+}
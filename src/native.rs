@@ -0,0 +1,73 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, OnceLock};
+
+use crate::metrics::rendered;
+
+// Resolution of the sparse buckets, each bucket is 2^(2^-SCHEMA) times wider
+// than the previous one, i.e. about 9% at schema 3
+pub const SCHEMA: i32 = 3;
+
+// Observations at or below this are counted in the zero bucket
+pub const ZERO_THRESHOLD: f64 = 2.938735877055719e-39;
+
+#[derive(Debug, Default, Clone)]
+pub struct NativeHistogram {
+    pub count: u64,
+    pub sum: f64,
+    pub zero_count: u64,
+    pub buckets: BTreeMap<i32, u64>,
+}
+
+impl NativeHistogram {
+    fn observe(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        if value <= ZERO_THRESHOLD {
+            self.zero_count += 1;
+            return;
+        }
+        // Bucket i holds values in (base^(i-1), base^i]
+        let index = (value.log2() * 2f64.powi(SCHEMA)).ceil() as i32;
+        *self.buckets.entry(index).or_default() += 1;
+    }
+}
+
+type Series = (String, Vec<(String, String)>);
+
+fn histograms() -> &'static Mutex<HashMap<Series, NativeHistogram>> {
+    static HISTOGRAMS: OnceLock<Mutex<HashMap<Series, NativeHistogram>>> = OnceLock::new();
+    HISTOGRAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+// Record an observation alongside the classic histogram of the same name.
+// Series are keyed by the name and labels they are rendered with.
+pub fn observe(name: &str, labels: &[(&str, String)], value: f64) {
+    let (name, labels) = rendered(name, labels);
+    let key = (
+        name,
+        sorted(labels.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+    );
+    histograms()
+        .lock()
+        .unwrap()
+        .entry(key)
+        .or_default()
+        .observe(value);
+}
+
+// Current state of a series, looked up by the labels it was rendered with
+pub fn get(name: &str, labels: &[(String, String)]) -> Option<NativeHistogram> {
+    let key = (
+        name.to_string(),
+        sorted(labels.iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+    );
+    histograms().lock().unwrap().get(&key).cloned()
+}
+
+fn sorted<'a>(labels: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<(String, String)> {
+    let mut labels: Vec<(String, String)> = labels
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    labels.sort();
+    labels
+}
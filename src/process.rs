@@ -0,0 +1,72 @@
+use std::fs;
+
+// Record process_* metrics for the exporter itself, as read from /proc
+#[cfg(target_os = "linux")]
+pub fn record() {
+    // Safety: sysconf has no preconditions
+    let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) } as f64;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as f64;
+
+    match fs::read_to_string("/proc/self/stat") {
+        Ok(stat) => {
+            // The command name may contain spaces, so split after its closing paren
+            let fields: Vec<&str> = match stat.rfind(')') {
+                Some(i) => stat[i + 2..].split_whitespace().collect(),
+                None => Vec::new(),
+            };
+            let field = |i: usize| -> f64 {
+                fields
+                    .get(i)
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .unwrap_or(0.0)
+            };
+
+            // Field offsets are relative to the state field, see proc(5)
+            let utime = field(11);
+            let stime = field(12);
+            let starttime = field(19);
+            let vsize = field(20);
+            let rss = field(21);
+
+            metrics::gauge!("process_cpu_seconds_total", (utime + stime) / ticks);
+            metrics::gauge!("process_virtual_memory_bytes", vsize);
+            metrics::gauge!("process_resident_memory_bytes", rss * page_size);
+
+            if let Some(btime) = boot_time() {
+                metrics::gauge!("process_start_time_seconds", btime + starttime / ticks);
+            }
+        }
+        Err(e) => log::debug!("\"Unable to read /proc/self/stat: {}\"", e),
+    };
+
+    if let Ok(fds) = fs::read_dir("/proc/self/fd") {
+        metrics::gauge!("process_open_fds", fds.count() as f64);
+    }
+
+    if let Some(max_fds) = max_fds() {
+        metrics::gauge!("process_max_fds", max_fds);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn record() {}
+
+// System boot time in seconds since the epoch
+#[cfg(target_os = "linux")]
+fn boot_time() -> Option<f64> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    stat.lines()
+        .find_map(|line| line.strip_prefix("btime "))
+        .and_then(|v| v.trim().parse().ok())
+}
+
+// Soft limit on open file descriptors
+#[cfg(target_os = "linux")]
+fn max_fds() -> Option<f64> {
+    let limits = fs::read_to_string("/proc/self/limits").ok()?;
+    limits
+        .lines()
+        .find(|line| line.starts_with("Max open files"))
+        .and_then(|line| line.split_whitespace().nth(3))
+        .and_then(|v| v.parse().ok())
+}
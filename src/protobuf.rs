@@ -0,0 +1,444 @@
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::collections::HashMap;
+
+use crate::metrics::{exemplar, rendered, Exemplar};
+use crate::native::{self, NativeHistogram};
+
+pub const PROTOBUF_CONTENT_TYPE: &str =
+    "application/vnd.google.protobuf; proto=io.prometheus.client.MetricFamily; encoding=delimited";
+
+// MetricType values from the Prometheus client model
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    Counter = 0,
+    Gauge = 1,
+    Summary = 2,
+    Untyped = 3,
+    Histogram = 4,
+}
+
+struct Family {
+    name: String,
+    kind: Kind,
+    samples: Vec<Sample>,
+}
+
+struct Sample {
+    name: String,
+    labels: Vec<(String, String)>,
+    value: f64,
+}
+
+// Render every metric in the delimited protobuf format, adding native
+// histogram buckets to the latency histograms tracked by crate::native.
+// Converted from the full text rendering.
+pub fn render(handle: &PrometheusHandle) -> Vec<u8> {
+    let (families, help) = parse(&handle.render());
+    let mut output = Vec::new();
+    for family in families {
+        let mut message = Vec::new();
+        string_field(&mut message, 1, &family.name);
+        if let Some(help) = help.get(&family.name) {
+            string_field(&mut message, 2, help);
+        }
+        uint_field(&mut message, 3, family.kind as u64);
+        for metric in encode_metrics(&family) {
+            bytes_field(&mut message, 4, &metric);
+        }
+        varint(&mut output, message.len() as u64);
+        output.extend_from_slice(&message);
+    }
+    output
+}
+
+// Split the text exposition into metric families and their help strings
+fn parse(text: &str) -> (Vec<Family>, HashMap<String, String>) {
+    let mut families: Vec<Family> = Vec::new();
+    let mut help = HashMap::new();
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            if let Some((name, text)) = rest.split_once(' ') {
+                help.insert(name.to_string(), text.to_string());
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            if let Some((name, kind)) = rest.split_once(' ') {
+                let kind = match kind {
+                    "counter" => Kind::Counter,
+                    "gauge" => Kind::Gauge,
+                    "summary" => Kind::Summary,
+                    "histogram" => Kind::Histogram,
+                    _ => Kind::Untyped,
+                };
+                families.push(Family {
+                    name: name.to_string(),
+                    kind,
+                    samples: Vec::new(),
+                });
+            }
+            continue;
+        }
+        if line.starts_with('#') {
+            continue;
+        }
+        let sample = match parse_sample(line) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        match families.last_mut() {
+            Some(family) if sample.name.starts_with(&family.name) => family.samples.push(sample),
+            _ => families.push(Family {
+                name: sample.name.clone(),
+                kind: Kind::Untyped,
+                samples: vec![sample],
+            }),
+        }
+    }
+    (families, help)
+}
+
+// Parse a line such as name{key="value"} 1.5
+fn parse_sample(line: &str) -> Option<Sample> {
+    let (name, rest) = match line.find(['{', ' ']) {
+        Some(i) => line.split_at(i),
+        None => return None,
+    };
+    let mut labels = Vec::new();
+    let mut rest = rest;
+    if let Some(inner) = rest.strip_prefix('{') {
+        let mut chars = inner.char_indices();
+        let mut key = String::new();
+        let mut end = None;
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '}' => {
+                    end = Some(i + 1);
+                    break;
+                }
+                ',' => continue,
+                '=' => {
+                    chars.next()?; // opening quote
+                    let mut value = String::new();
+                    loop {
+                        match chars.next()?.1 {
+                            '\\' => match chars.next()?.1 {
+                                'n' => value.push('\n'),
+                                c => value.push(c),
+                            },
+                            '"' => break,
+                            c => value.push(c),
+                        }
+                    }
+                    labels.push((std::mem::take(&mut key), value));
+                }
+                c => key.push(c),
+            }
+        }
+        rest = &inner[end?..];
+    }
+    let value = rest.split_whitespace().next()?.parse::<f64>().ok()?;
+    Some(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+    })
+}
+
+fn encode_metrics(family: &Family) -> Vec<Vec<u8>> {
+    match family.kind {
+        Kind::Histogram | Kind::Summary => encode_distributions(family),
+        kind => family
+            .samples
+            .iter()
+            .map(|sample| {
+                let mut metric = labels_message(&sample.labels);
+                let mut value = Vec::new();
+                double_field(&mut value, 1, sample.value);
+                let field = match kind {
+                    Kind::Counter => 3,
+                    Kind::Gauge => 2,
+                    _ => 5,
+                };
+                bytes_field(&mut metric, field, &value);
+                metric
+            })
+            .collect(),
+    }
+}
+
+// Classic histogram buckets or summary quantiles, grouped per label set
+#[derive(Default)]
+struct Distribution {
+    count: u64,
+    sum: f64,
+    points: Vec<(f64, f64)>,
+}
+
+fn encode_distributions(family: &Family) -> Vec<Vec<u8>> {
+    let split = match family.kind {
+        Kind::Histogram => "le",
+        _ => "quantile",
+    };
+    let exemplar_metric = rendered("http_requests_duration_seconds", &[]).0;
+    let mut series: Vec<(Vec<(String, String)>, Distribution)> = Vec::new();
+    for sample in &family.samples {
+        let mut labels = sample.labels.clone();
+        let point = labels
+            .iter()
+            .position(|(k, _)| k == split)
+            .map(|i| labels.remove(i).1);
+        let index = match series.iter().position(|(l, _)| *l == labels) {
+            Some(index) => index,
+            None => {
+                series.push((labels, Distribution::default()));
+                series.len() - 1
+            }
+        };
+        let distribution = &mut series[index].1;
+        let suffix = &sample.name[family.name.len()..];
+        match (suffix, point) {
+            ("_sum", _) => distribution.sum = sample.value,
+            ("_count", _) => distribution.count = sample.value as u64,
+            // The +Inf bucket is implied by the sample count
+            (_, Some(point)) if point != "+Inf" => {
+                if let Ok(point) = point.parse::<f64>() {
+                    distribution.points.push((point, sample.value));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    series
+        .into_iter()
+        .map(|(labels, distribution)| {
+            let mut metric = labels_message(&labels);
+            let mut value = Vec::new();
+            uint_field(&mut value, 1, distribution.count);
+            double_field(&mut value, 2, distribution.sum);
+            for (point, count) in &distribution.points {
+                let mut entry = Vec::new();
+                match family.kind {
+                    Kind::Histogram => {
+                        uint_field(&mut entry, 1, *count as u64);
+                        double_field(&mut entry, 2, *point);
+                        if *family.name == exemplar_metric {
+                            if let Some(exemplar) = exemplar(&labels, &point.to_string()) {
+                                bytes_field(&mut entry, 3, &exemplar_message(&exemplar));
+                            }
+                        }
+                    }
+                    _ => {
+                        double_field(&mut entry, 1, *point);
+                        double_field(&mut entry, 2, *count);
+                    }
+                }
+                bytes_field(&mut value, 3, &entry);
+            }
+            if family.kind == Kind::Histogram {
+                if let Some(histogram) = native::get(&family.name, &labels) {
+                    encode_native(&mut value, &histogram);
+                }
+            }
+            let field = match family.kind {
+                Kind::Histogram => 7,
+                _ => 4,
+            };
+            bytes_field(&mut metric, field, &value);
+            metric
+        })
+        .collect()
+}
+
+// Sparse buckets as spans of consecutive indexes plus delta encoded counts, in
+// the positive_span (12) and positive_delta (13) fields of a Histogram
+fn encode_native(value: &mut Vec<u8>, histogram: &NativeHistogram) {
+    sint_field(value, 5, native::SCHEMA as i64);
+    double_field(value, 6, native::ZERO_THRESHOLD);
+    uint_field(value, 7, histogram.zero_count);
+
+    let mut spans: Vec<(i32, u32)> = Vec::new();
+    let mut deltas = Vec::new();
+    let mut previous: Option<(i32, u64)> = None;
+    for (&index, &count) in &histogram.buckets {
+        match previous {
+            Some((last, _)) if index == last + 1 => {
+                if let Some(span) = spans.last_mut() {
+                    span.1 += 1;
+                }
+            }
+            Some((last, _)) => spans.push((index - last - 1, 1)),
+            None => spans.push((index, 1)),
+        }
+        let last_count = previous.map(|(_, c)| c).unwrap_or(0);
+        deltas.push(count as i64 - last_count as i64);
+        previous = Some((index, count));
+    }
+    // An empty span still marks the histogram as native
+    if spans.is_empty() {
+        spans.push((0, 0));
+    }
+
+    for (offset, length) in spans {
+        let mut span = Vec::new();
+        sint_field(&mut span, 1, offset as i64);
+        uint_field(&mut span, 2, length as u64);
+        bytes_field(value, 12, &span);
+    }
+    if !deltas.is_empty() {
+        let mut packed = Vec::new();
+        for delta in deltas {
+            varint(&mut packed, zigzag(delta));
+        }
+        bytes_field(value, 13, &packed);
+    }
+}
+
+fn exemplar_message(exemplar: &Exemplar) -> Vec<u8> {
+    let mut message = labels_message(&[("trace_id".to_string(), exemplar.trace_id.clone())]);
+    double_field(&mut message, 2, exemplar.value);
+    let mut timestamp = Vec::new();
+    uint_field(&mut timestamp, 1, exemplar.timestamp.trunc() as u64);
+    uint_field(&mut timestamp, 2, (exemplar.timestamp.fract() * 1e9) as u64);
+    bytes_field(&mut message, 3, &timestamp);
+    message
+}
+
+fn labels_message(labels: &[(String, String)]) -> Vec<u8> {
+    let mut metric = Vec::new();
+    for (name, value) in labels {
+        let mut pair = Vec::new();
+        string_field(&mut pair, 1, name);
+        string_field(&mut pair, 2, value);
+        bytes_field(&mut metric, 1, &pair);
+    }
+    metric
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+fn uint_field(buf: &mut Vec<u8>, field: u64, value: u64) {
+    varint(buf, field << 3);
+    varint(buf, value);
+}
+
+fn sint_field(buf: &mut Vec<u8>, field: u64, value: i64) {
+    uint_field(buf, field, zigzag(value));
+}
+
+fn double_field(buf: &mut Vec<u8>, field: u64, value: f64) {
+    varint(buf, (field << 3) | 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn bytes_field(buf: &mut Vec<u8>, field: u64, value: &[u8]) {
+    varint(buf, (field << 3) | 2);
+    varint(buf, value.len() as u64);
+    buf.extend_from_slice(value);
+}
+
+fn string_field(buf: &mut Vec<u8>, field: u64, value: &str) {
+    bytes_field(buf, field, value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::{Key, Label, Recorder};
+    use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+
+    // The parts of io.prometheus.client's metrics.proto the encoder writes
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct MetricFamily {
+        #[prost(string, tag = "1")]
+        name: String,
+        #[prost(int32, tag = "3")]
+        kind: i32,
+        #[prost(message, repeated, tag = "4")]
+        metric: Vec<Metric>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Metric {
+        #[prost(message, optional, tag = "7")]
+        histogram: Option<Histogram>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Histogram {
+        #[prost(uint64, tag = "1")]
+        sample_count: u64,
+        #[prost(double, tag = "2")]
+        sample_sum: f64,
+        #[prost(sint32, tag = "5")]
+        schema: i32,
+        #[prost(double, tag = "6")]
+        zero_threshold: f64,
+        #[prost(uint64, tag = "7")]
+        zero_count: u64,
+        #[prost(double, repeated, tag = "11")]
+        negative_count: Vec<f64>,
+        #[prost(message, repeated, tag = "12")]
+        positive_span: Vec<BucketSpan>,
+        #[prost(sint64, repeated, tag = "13")]
+        positive_delta: Vec<i64>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct BucketSpan {
+        #[prost(sint32, tag = "1")]
+        offset: i32,
+        #[prost(uint32, tag = "2")]
+        length: u32,
+    }
+
+    #[test]
+    fn native_buckets_decode_as_metrics_proto() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(
+                Matcher::Full("ece_api_request_duration_seconds".to_string()),
+                &[0.25, 1.0],
+            )
+            .unwrap()
+            .build_recorder();
+        let key = Key::from_parts(
+            "ece_api_request_duration_seconds",
+            vec![Label::new("path", "protobuf/round_trip")],
+        );
+        let histogram = recorder.register_histogram(&key);
+        let labels = [("path", "protobuf/round_trip".to_string())];
+        for value in [0.1, 0.1, 0.5] {
+            histogram.record(value);
+            native::observe("ece_api_request_duration_seconds", &labels, value);
+        }
+
+        let output = render(&recorder.handle());
+        let family = <MetricFamily as prost::Message>::decode_length_delimited(&*output).unwrap();
+        assert_eq!(family.name, "ece_api_request_duration_seconds");
+        assert_eq!(family.kind, Kind::Histogram as i32);
+        let histogram = family.metric[0].histogram.clone().unwrap();
+        assert_eq!(histogram.sample_count, 3);
+        assert_eq!(histogram.schema, native::SCHEMA);
+        assert_eq!(histogram.zero_count, 0);
+        assert!(histogram.negative_count.is_empty());
+        // 0.1 lands in bucket -26 and 0.5 in bucket -8 at schema 3
+        let spans: Vec<(i32, u32)> = histogram
+            .positive_span
+            .iter()
+            .map(|span| (span.offset, span.length))
+            .collect();
+        assert_eq!(spans, vec![(-26, 1), (17, 1)]);
+        assert_eq!(histogram.positive_delta, vec![2, -1]);
+    }
+}
@@ -0,0 +1,66 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::credentials::Credentials;
+
+// How often mounted secret files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Copy, Debug)]
+pub enum SecretField {
+    ApiKey,
+    Password,
+}
+
+impl SecretField {
+    pub fn set(&self, credentials: &mut Credentials, value: String) {
+        match self {
+            SecretField::ApiKey => credentials.api_key = Some(value),
+            SecretField::Password => credentials.password = Some(value),
+        }
+    }
+}
+
+pub fn read_secret(path: &str) -> io::Result<String> {
+    Ok(fs::read_to_string(path)?.trim().to_string())
+}
+
+// Identity of the file currently behind the path. Kubernetes rotates projected
+// secrets by swapping the ..data symlink, which changes the resolved path.
+fn fingerprint(path: &Path) -> Option<(PathBuf, SystemTime)> {
+    let resolved = fs::canonicalize(path).ok()?;
+    let modified = fs::metadata(&resolved).ok()?.modified().ok()?;
+    Some((resolved, modified))
+}
+
+// Watch a secret file, reloading the credential as soon as it changes
+pub fn spawn_watch(path: String, field: SecretField, credentials: Arc<RwLock<Credentials>>) {
+    tokio::spawn(async move {
+        let mut last = fingerprint(Path::new(&path));
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let current = fingerprint(Path::new(&path));
+            if current.is_none() || current == last {
+                continue;
+            }
+            match read_secret(&path) {
+                Ok(value) => {
+                    field.set(&mut credentials.write().unwrap(), value);
+                    log::info!(
+                        "{{\"event\": \"credentials rotated\", \"path\": \"{}\"}}",
+                        path
+                    );
+                    last = current;
+                }
+                Err(e) => log::error!(
+                    "{{\"msg\": \"unable to reload secret file\", \"path\": \"{}\", \"error\": \"{}\"}}",
+                    path,
+                    e
+                ),
+            }
+        }
+    });
+}
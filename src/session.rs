@@ -0,0 +1,90 @@
+use hyper::header::{HeaderValue, CONTENT_TYPE, USER_AGENT};
+use hyper::{Method, Request};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::api::EceApi;
+use crate::body;
+use crate::error::{parse_api_errors, Error as RestError};
+use crate::https::RequestBody;
+
+// Path used to exchange a username and password for a session token
+pub const LOGIN_PATH: &str = "api/v1/users/auth/_login";
+
+// Log in again this long before the token expires
+const EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+// ECE session token, obtained by logging in once rather than sending basic
+// auth on every request
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub token: String,
+    pub expires: Option<SystemTime>,
+}
+
+#[derive(Deserialize)]
+struct LoginResponse {
+    token: String,
+}
+
+impl Session {
+    pub fn authorization(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!("Bearer {}", self.token))
+            .expect("failed to convert session header")
+    }
+
+    // Whether the token has expired or is about to
+    pub fn expiring(&self) -> bool {
+        match self.expires {
+            Some(expires) => SystemTime::now() + EXPIRY_MARGIN >= expires,
+            None => false,
+        }
+    }
+}
+
+// Read the exp claim from a JWT without verifying it, ECE does that
+fn expiry(token: &str) -> Option<SystemTime> {
+    let payload = token.split('.').nth(1)?;
+    let bytes = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+    let claims: Value = serde_json::from_slice(&bytes).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(claims["exp"].as_u64()?))
+}
+
+pub async fn login(
+    client: &dyn EceApi,
+    url: &str,
+    user_agent: &HeaderValue,
+    username: &str,
+    password: &str,
+) -> Result<Session, RestError> {
+    let payload = json!({"username": username, "password": password});
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(format!("{}/{}", url, LOGIN_PATH))
+        .header(CONTENT_TYPE, "application/json")
+        .header(USER_AGENT, user_agent)
+        .body(RequestBody::from(payload.to_string()))
+        .expect("request builder");
+
+    let timed_out = || RestError::GatewayTimeout("no response from ECE to login".to_string());
+    let deadline = tokio::time::Instant::now() + client.request_timeout();
+    let response = tokio::time::timeout_at(deadline, client.send(req))
+        .await
+        .map_err(|_| timed_out())??;
+    let status = response.status().as_u16();
+    let bytes = tokio::time::timeout_at(deadline, body::to_bytes(response.into_body()))
+        .await
+        .map_err(|_| timed_out())??;
+    if status != 200 {
+        let (code, message) = parse_api_errors(&bytes);
+        return Err(RestError::from_status(status, code, message));
+    }
+
+    let LoginResponse { token } = serde_json::from_slice(&bytes)?;
+    log::info!("\"Logged in to ECE with a session token\"");
+    Ok(Session {
+        expires: expiry(&token),
+        token,
+    })
+}
@@ -0,0 +1,130 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Last good ECE responses keyed by API path, persisted so that a restarted
+// exporter can serve metrics before ECE has been reached again
+#[derive(Debug)]
+pub struct Snapshot {
+    path: PathBuf,
+    latest: Mutex<Map<String, Value>>,
+    // Responses read at startup, each dropped once its path is fetched live
+    restored: Mutex<HashMap<String, Value>>,
+    // Size of the snapshot as last read or written
+    bytes: AtomicU64,
+    // Set by store and cleared once flushed to disk
+    dirty: AtomicBool,
+    // Held across each write and rename
+    writer: Mutex<()>,
+    // Numbers the temporary files
+    writes: AtomicU64,
+}
+
+impl Snapshot {
+    pub fn load(path: &str) -> Self {
+        let mut size = 0;
+        let latest = match fs::read(path) {
+            Ok(bytes) => match serde_json::from_slice::<Map<String, Value>>(&bytes) {
+                Ok(latest) => {
+                    log::info!("\"Restored {} ECE responses from {}\"", latest.len(), path);
+                    size = bytes.len() as u64;
+                    latest
+                }
+                Err(e) => {
+                    log::warn!(
+                        "{{\"path\": \"{}\", \"error\": \"unable to parse snapshot: {}\"}}",
+                        path,
+                        e
+                    );
+                    Map::new()
+                }
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Map::new(),
+            Err(e) => {
+                log::warn!(
+                    "{{\"path\": \"{}\", \"error\": \"unable to read snapshot: {}\"}}",
+                    path,
+                    e
+                );
+                Map::new()
+            }
+        };
+        Snapshot {
+            path: PathBuf::from(path),
+            restored: Mutex::new(latest.clone().into_iter().collect()),
+            latest: Mutex::new(latest),
+            bytes: AtomicU64::new(size),
+            dirty: AtomicBool::new(false),
+            writer: Mutex::new(()),
+            writes: AtomicU64::new(0),
+        }
+    }
+
+    // Response persisted by a previous run and when it was fetched, if this
+    // path has not been fetched since
+    pub fn restored<T: DeserializeOwned>(&self, key: &str) -> Option<(T, SystemTime)> {
+        let mut entry = self.restored.lock().unwrap().get(key).cloned()?;
+        let fetched = UNIX_EPOCH + Duration::from_secs(entry["fetched"].as_u64()?);
+        let value = serde_json::from_value(entry["value"].take()).ok()?;
+        Some((value, fetched))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Serialized size of the responses held, as an estimate of their footprint
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    // Record a live response, written out by the next flush
+    pub fn store(&self, key: &str, value: Value) {
+        self.restored.lock().unwrap().remove(key);
+        let fetched = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut latest = self.latest.lock().unwrap();
+        latest.insert(key.to_string(), json!({"fetched": fetched, "value": value}));
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    // Rewrite the snapshot file if anything was stored since the last flush,
+    // one writer at a time. Blocks on disk I/O.
+    pub fn flush(&self) -> io::Result<()> {
+        let _writer = self.writer.lock().unwrap();
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let written = self.write();
+        if written.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        written
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let bytes = serde_json::to_vec(&*self.latest.lock().unwrap())?;
+        self.bytes.store(bytes.len() as u64, Ordering::Relaxed);
+
+        // Write to a temporary file first so a crash never leaves a torn snapshot
+        let tmp = self.path.with_extension(format!(
+            "tmp.{}.{}",
+            process::id(),
+            self.writes.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = fs::write(&tmp, bytes).and_then(|_| fs::rename(&tmp, &self.path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        written
+    }
+}
@@ -1,358 +1,1317 @@
 use chrono::DateTime;
-use chrono::Datelike;
-use chrono::NaiveDate;
 use chrono::Utc;
-use clap::ArgMatches;
-use http_auth_basic::Credentials;
-use hyper::header::HeaderValue;
-use hyper::header::AUTHORIZATION;
-use hyper::{Body, Request, Response};
+use flate2::read::GzDecoder;
+use hyper::body::Bytes;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::header::{
+    ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+    IF_NONE_MATCH, RETRY_AFTER, USER_AGENT,
+};
+use hyper::{Method, Request, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
+use std::io::{BufReader, Read};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Notify;
 
-use crate::error::Error as RestError;
-use crate::https::{ClientBuilder, HttpsClient};
-use crate::{allocator, proxy};
+use crate::api::EceApi;
+use crate::availability::Availability;
+#[cfg(feature = "aws")]
+use crate::aws;
+use crate::body::{BodyReader, LimitedReader, TeeReader};
+use crate::collector::{Collection, Registry};
+use crate::config::{AllocatorLabel, Auth, CostFormat, Password, Settings};
+#[cfg(feature = "cost")]
+use crate::cost::InstanceCosts;
+use crate::credentials::Credentials;
+use crate::diagnostics::Diagnostics;
+use crate::error::{parse_api_errors, Error as RestError};
+use crate::events::{Event, HealthEvents};
+#[cfg(feature = "deployments")]
+use crate::https::HttpsClient;
+use crate::https::{ClientBuilder, RequestBody, ResponseBody};
+use crate::names::{InstanceConfiguration, Names};
+use crate::native;
+use crate::secret_file::{self, SecretField};
+use crate::snapshot::Snapshot;
+use crate::template::LabelTemplate;
+use crate::vault;
+#[cfg(feature = "push")]
+use crate::webhook::Notifier;
+use crate::{allocator, deployment, session};
 
 type BoxResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
 
+// Backoff applied when ECE rate limits without a usable Retry-After
+const DEFAULT_RETRY_AFTER: u64 = 60;
+
+// Collectors only built with the deployments feature
+pub const DEPLOYMENT_COLLECTORS: &[&str] = &[
+    "deployments",
+    "endpoints",
+    "remote_clusters",
+    "cluster_health",
+    "shard_allocation",
+    "jvm_heap",
+    "indices",
+    "slm",
+    "ilm",
+];
+
+// ECE API paths whose raw responses are kept for the /debug endpoints
+pub const ALLOCATORS_PATH: &str = "api/v1/platform/infrastructure/allocators";
+pub const PROXIES_PATH: &str = "api/v1/platform/infrastructure/proxies";
+pub const ELASTICSEARCH_PATH: &str = "api/v1/clusters/elasticsearch";
+pub const KIBANA_PATH: &str = "api/v1/clusters/kibana";
+pub const DEPLOYMENTS_PATH: &str = "api/v1/deployments";
+#[cfg(feature = "deployments")]
+pub const DEPLOYMENTS_SEARCH_PATH: &str = "api/v1/deployments/_search";
+pub const PLATFORM_PATH: &str = "api/v1/platform";
+#[cfg(feature = "push")]
+const LICENSE_PATH: &str = "api/v1/platform/license";
+pub const INSTANCE_CONFIGURATIONS_PATH: &str = "api/v1/platform/configuration/instances";
+const DEBUG_PATHS: &[&str] = &[ALLOCATORS_PATH, PROXIES_PATH];
+// Snapshot entry keeping ece_allocator_disconnected_seconds across restarts
+pub const DISCONNECTED_KEY: &str = "exporter/allocators_disconnected_since";
+
 #[derive(Clone, Debug)]
 pub struct State {
-    pub client: HttpsClient,
+    pub client: Arc<dyn EceApi>,
     pub url: String,
-    pub username: Option<String>,
-    pub password: Option<String>,
-    pub api_key: Option<String>,
+    pub credentials: Arc<RwLock<Credentials>>,
+    pub headers: Arc<Vec<(HeaderName, HeaderValue)>>,
+    pub user_agent: HeaderValue,
     pub eru_cost: u64,
+    pub cost_format: CostFormat,
+    pub eru_size_gb: u64,
+    pub eru_size_overrides: Arc<HashMap<String, u64>>,
+    pub collectors: Vec<String>,
+    pub registry: Arc<Registry>,
+    pub max_response_bytes: u64,
+    pub max_concurrent_requests: usize,
+    pub max_concurrent_scrapes: usize,
+    pub server_timeout: Duration,
+    pub mb_metrics: bool,
+    pub allocator_label: AllocatorLabel,
+    pub aggregate_only: bool,
+    pub deployment_query: Arc<Value>,
+    pub probe_timeout: Duration,
+    pub tag_metrics: Arc<HashMap<String, String>>,
+    pub instance_labels: Arc<Vec<LabelTemplate>>,
+    pub backoff_until: Arc<Mutex<Option<Instant>>>,
+    pub auth_cooldown: Duration,
+    pub auth_failure: Arc<Mutex<Option<AuthFailure>>>,
+    pub session_auth: bool,
+    pub login_lock: Arc<tokio::sync::Mutex<()>>,
+    pub request_slots: Arc<tokio::sync::Semaphore>,
+    // Held by each /metrics render, beyond which scrapes are turned away
+    pub scrape_slots: Arc<tokio::sync::Semaphore>,
+    pub name_cache_ttl: Duration,
+    pub name_cache: Arc<Mutex<NameCache>>,
+    pub stale_while_revalidate: Duration,
+    pub last_good: Arc<Mutex<HashMap<String, LastGood>>>,
+    pub served_stale: Arc<AtomicBool>,
+    pub last_success: Arc<Mutex<Option<Instant>>>,
+    pub platform_version: Arc<Mutex<Option<String>>>,
+    pub etag_cache: Arc<Mutex<HashMap<String, CachedResponse>>>,
+    pub snapshot: Option<Arc<Snapshot>>,
+    pub diagnostics: Arc<Mutex<Diagnostics>>,
+    pub availability: Arc<Mutex<Availability>>,
+    #[cfg(feature = "cost")]
+    pub instance_costs: Arc<Mutex<InstanceCosts>>,
+    pub health_events: Arc<Mutex<HealthEvents>>,
+    #[cfg(feature = "push")]
+    pub notifier: Option<Notifier>,
+    #[cfg(feature = "deployments")]
+    pub prober: HttpsClient,
+    pub debug_token: Option<String>,
+    pub raw_responses: Arc<Mutex<HashMap<String, Bytes>>>,
+    pub refresh: Arc<Notify>,
+    pub last_collect: Arc<tokio::sync::Mutex<Option<(Instant, bool)>>>,
+    pub fetched_at: Arc<Mutex<HashMap<String, SystemTime>>>,
+    // Decoded size of the last full response for each path, as an estimate
+    // of the memory its parsed value holds in the caches
+    pub response_bytes: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+// Last parsed response for a GET path, reused when ECE answers 304 Not Modified
+#[derive(Clone)]
+pub struct CachedResponse {
+    pub etag: HeaderValue,
+    pub value: Arc<dyn Any + Send + Sync>,
+}
+
+impl std::fmt::Debug for CachedResponse {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CachedResponse")
+            .field("etag", &self.etag)
+            .finish()
+    }
+}
+
+// Last successfully parsed response for a path, served while a refresh fails
+#[derive(Clone)]
+pub struct LastGood {
+    pub fetched: Instant,
+    pub value: Arc<dyn Any + Send + Sync>,
+}
+
+impl std::fmt::Debug for LastGood {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LastGood")
+            .field("fetched", &self.fetched)
+            .finish()
+    }
+}
+
+// Credentials ECE last rejected, and until when to stop retrying them
+#[derive(Debug)]
+pub struct AuthFailure {
+    pub until: Instant,
+    pub authorization: Option<HeaderValue>,
+}
+
+// Names for deployment, cluster and instance configuration ids, refreshed
+// once older than name_cache_ttl
+#[derive(Debug, Default)]
+pub struct NameCache {
+    pub names: Arc<Names>,
+    pub fetched: Option<Instant>,
 }
 
 impl State {
-    pub async fn new(opts: ArgMatches) -> BoxResult<Self> {
-        // Set timeout
-        let timeout: u64 = opts
-            .value_of("timeout")
-            .unwrap()
-            .parse()
-            .unwrap_or_else(|_| {
-                eprintln!("Supplied timeout not in range, defaulting to 60");
-                60
-            });
-
-        let eru_cost: u64 = opts
-            .value_of("eru_cost")
-            .unwrap()
-            .parse()
-            .unwrap_or_else(|_| {
-                eprintln!("ERU cost is not with available range, defaulting to 6000");
-                60
-            });
+    pub async fn new(settings: &Settings) -> BoxResult<Self> {
+        let client = ClientBuilder::new()
+            .connect_timeout(settings.connect_timeout)
+            .request_timeout(settings.request_timeout)
+            .build()?;
+        Self::with_api(settings, Arc::new(client)).await
+    }
 
-        let client = ClientBuilder::new().timeout(timeout).build()?;
+    // State talking to ECE through the given transport
+    pub async fn with_api(settings: &Settings, api: Arc<dyn EceApi>) -> BoxResult<Self> {
+        let mut credentials = Credentials::default();
+        let mut secret_files = Vec::new();
+        let mut vault_config = None;
+        match &settings.auth {
+            Auth::ApiKey(key) => credentials.api_key = Some(key.clone()),
+            // Secrets mounted as files are read now and reloaded when they change
+            Auth::ApiKeyFile(path) => secret_files.push((path.clone(), SecretField::ApiKey)),
+            #[cfg(feature = "aws")]
+            Auth::ApiKeyFrom(source) => {
+                credentials.api_key = Some(aws::fetch_secret(source).await?);
+                log::info!("\"Loaded ECE api key from {}\"", source);
+            }
+            // Fetch the api key from vault up front, then keep it refreshed
+            // Vault is not ECE, so it gets a client of its own
+            Auth::Vault(config) => {
+                let client = ClientBuilder::new()
+                    .connect_timeout(settings.connect_timeout)
+                    .request_timeout(settings.request_timeout)
+                    .build()?;
+                credentials.api_key = Some(vault::fetch_api_key(&client, config).await?);
+                log::info!("\"Loaded ECE api key from vault\"");
+                vault_config = Some((client, config.clone()));
+            }
+            Auth::Basic { username, password } => {
+                credentials.username = Some(username.clone());
+                match password {
+                    Password::Value(password) => credentials.password = Some(password.clone()),
+                    Password::File(path) => {
+                        secret_files.push((path.clone(), SecretField::Password))
+                    }
+                }
+            }
+        }
+        for (path, field) in &secret_files {
+            let value = secret_file::read_secret(path)
+                .map_err(|e| format!("unable to read secret file {}: {}", path, e))?;
+            field.set(&mut credentials, value);
+        }
+
+        let credentials = Arc::new(RwLock::new(credentials));
+        if let Some((client, config)) = vault_config {
+            vault::spawn_refresh(client, config, credentials.clone());
+        }
+        for (path, field) in secret_files {
+            secret_file::spawn_watch(path, field, credentials.clone());
+        }
+
+        // The webhook is not ECE either
+        #[cfg(feature = "push")]
+        let notifier = match &settings.webhook_url {
+            Some(url) => {
+                let client = ClientBuilder::new()
+                    .connect_timeout(settings.connect_timeout)
+                    .request_timeout(settings.request_timeout)
+                    .build()?;
+                Some(Notifier::new(
+                    client,
+                    url.clone(),
+                    settings.license_expiry_warning,
+                ))
+            }
+            None => None,
+        };
+
+        // Nor are the deployment endpoints, which must not see the ECE credentials
+        #[cfg(feature = "deployments")]
+        let prober = ClientBuilder::new()
+            .connect_timeout(settings.probe_timeout)
+            .request_timeout(settings.probe_timeout)
+            .build()?;
+
+        let snapshot = settings
+            .snapshot_file
+            .as_deref()
+            .map(|path| Arc::new(Snapshot::load(path)));
+        let mut health_events = HealthEvents::default();
+        if let Some((since, _)) = snapshot
+            .as_ref()
+            .and_then(|snapshot| snapshot.restored(DISCONNECTED_KEY))
+        {
+            health_events.restore_disconnected(since);
+        }
 
         Ok(State {
-            client,
-            url: opts.value_of("url").unwrap().to_string(),
-            username: opts.value_of("username").map(str::to_string),
-            password: opts.value_of("password").map(str::to_string),
-            api_key: opts.value_of("apikey").map(str::to_string),
-            eru_cost,
+            client: api,
+            url: settings.url.clone(),
+            credentials,
+            headers: Arc::new(settings.headers.clone()),
+            user_agent: settings.user_agent.clone(),
+            eru_cost: settings.eru_cost,
+            cost_format: settings.cost_format,
+            eru_size_gb: settings.eru_size_gb,
+            eru_size_overrides: Arc::new(settings.eru_size_overrides.clone()),
+            collectors: settings.collectors.clone(),
+            registry: Arc::new({
+                let mut registry = Registry::builtin();
+                for collector in &settings.custom_collectors {
+                    registry.register(collector.clone());
+                }
+                registry
+            }),
+            max_response_bytes: settings.max_response_bytes,
+            max_concurrent_requests: settings.max_concurrent_requests,
+            max_concurrent_scrapes: settings.max_concurrent_scrapes,
+            server_timeout: Duration::from_secs(settings.server_timeout),
+            mb_metrics: settings.mb_metrics,
+            allocator_label: settings.allocator_label,
+            aggregate_only: settings.aggregate_only,
+            deployment_query: Arc::new(settings.deployment_query.clone()),
+            probe_timeout: Duration::from_secs(settings.probe_timeout),
+            tag_metrics: Arc::new(settings.tag_metrics.clone()),
+            instance_labels: Arc::new(settings.instance_labels.clone()),
+            backoff_until: Arc::new(Mutex::new(None)),
+            auth_cooldown: settings.auth_failure_cooldown,
+            auth_failure: Arc::new(Mutex::new(None)),
+            session_auth: settings.session_auth,
+            login_lock: Arc::new(tokio::sync::Mutex::new(())),
+            request_slots: Arc::new(tokio::sync::Semaphore::new(
+                settings.max_concurrent_requests,
+            )),
+            scrape_slots: Arc::new(tokio::sync::Semaphore::new(settings.max_concurrent_scrapes)),
+            name_cache_ttl: settings.name_cache_ttl,
+            name_cache: Arc::new(Mutex::new(NameCache::default())),
+            stale_while_revalidate: settings.stale_while_revalidate,
+            last_good: Arc::new(Mutex::new(HashMap::new())),
+            served_stale: Arc::new(AtomicBool::new(false)),
+            last_success: Arc::new(Mutex::new(None)),
+            platform_version: Arc::new(Mutex::new(None)),
+            etag_cache: Arc::new(Mutex::new(HashMap::new())),
+            snapshot,
+            diagnostics: Arc::new(Mutex::new(Diagnostics::default())),
+            availability: Arc::new(Mutex::new(Availability::default())),
+            #[cfg(feature = "cost")]
+            instance_costs: Arc::new(Mutex::new(InstanceCosts::default())),
+            health_events: Arc::new(Mutex::new(health_events)),
+            #[cfg(feature = "push")]
+            notifier,
+            #[cfg(feature = "deployments")]
+            prober,
+            debug_token: settings.debug_token.clone(),
+            raw_responses: Arc::new(Mutex::new(HashMap::new())),
+            refresh: Arc::new(Notify::new()),
+            last_collect: Arc::new(tokio::sync::Mutex::new(None)),
+            fetched_at: Arc::new(Mutex::new(HashMap::new())),
+            response_bytes: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
-    pub async fn get(&self, path: &str) -> Result<Response<Body>, RestError> {
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        payload: Option<Value>,
+        conditional: Option<HeaderValue>,
+    ) -> Result<(Response<ResponseBody>, tokio::time::Instant), RestError> {
+        // Honor any outstanding rate limit backoff before contacting ECE
+        if let Some(until) = *self.backoff_until.lock().unwrap() {
+            let now = Instant::now();
+            if until > now {
+                let remaining = (until - now).as_secs() + 1;
+                log::debug!(
+                    "\"Skipping {} while rate limited for {}s\"",
+                    path,
+                    remaining
+                );
+                return Err(RestError::RateLimited(remaining));
+            }
+        }
+
+        // Stop retrying credentials ECE rejected until they change or the cooldown ends,
+        // so a bad credential does not lock the account
+        let authorization = self.credentials.read().unwrap().authorization();
+        if let Some(failure) = &*self.auth_failure.lock().unwrap() {
+            let now = Instant::now();
+            if failure.until > now && failure.authorization == authorization {
+                metrics::gauge!("ece_auth_failed", 1f64);
+                let remaining = (failure.until - now).as_secs() + 1;
+                return Err(RestError::AuthCooldown(remaining));
+            }
+        }
+
         let uri = format!("{}/{}", &self.url, path);
-        log::debug!("getting url {}", &uri);
+        log::debug!("{} url {}", &method, &uri);
+
+        if self.session_auth {
+            self.ensure_session(false, path, &authorization).await?;
+        }
+
+        // With session auth, a rejected token is renewed once before giving up
+        let mut relogin = self.session_auth;
+        let (response, deadline) = loop {
+            let req = self.build_request(&method, &uri, &payload, conditional.clone());
+            let (response, deadline) = self.send(req, path).await?;
+            if response.status() == StatusCode::UNAUTHORIZED && relogin {
+                relogin = false;
+                log::info!(
+                    "\"ECE rejected the session token on {}, logging in again\"",
+                    path
+                );
+                self.ensure_session(true, path, &authorization).await?;
+                continue;
+            }
+            break (response, deadline);
+        };
+
+        let status = response.status().as_u16();
+        if status == 200 || status == 304 {
+            metrics::gauge!("ece_auth_failed", 0f64);
+            return Ok((response, deadline));
+        }
+
+        if status == 401 || status == 403 {
+            self.record_auth_failure(path, authorization);
+        }
+
+        if status == 429 {
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after)
+                .unwrap_or(DEFAULT_RETRY_AFTER);
+            log::warn!(
+                "\"Rate limited by ECE on {}, backing off for {}s\"",
+                path,
+                retry_after
+            );
+            *self.backoff_until.lock().unwrap() =
+                Some(Instant::now() + Duration::from_secs(retry_after));
+            metrics::increment_counter!("ece_api_rate_limited_total");
+            return Err(RestError::RateLimited(retry_after));
+        }
+
+        // Error bodies are bounded like any other, compressed or not
+        let gzip = is_gzip(&response);
+        let max_bytes = self.max_response_bytes;
+        let reader = BodyReader::new(response.into_body(), max_bytes, deadline);
+        let timed_out = reader.timed_out();
+        let bytes = tokio::task::spawn_blocking(move || {
+            let mut reader: Box<dyn Read> = match gzip {
+                true => Box::new(LimitedReader::new(
+                    GzDecoder::new(reader),
+                    max_bytes,
+                    Arc::new(AtomicU64::new(0)),
+                )),
+                false => Box::new(reader),
+            };
+            // An oversized or unreadable body still reports the status, just
+            // without ECE's error code and message
+            let mut bytes = Vec::new();
+            match reader.read_to_end(&mut bytes) {
+                Ok(_) => bytes,
+                Err(_) => Vec::new(),
+            }
+        })
+        .await
+        .expect("error body read panicked");
+        if timed_out.load(Ordering::Relaxed) {
+            return Err(self.timed_out(path));
+        }
+        let (code, message) = parse_api_errors(&bytes);
+        let labels = [("status", status.to_string()), ("code", code.to_owned())];
+        metrics::increment_counter!("ece_api_errors_total", &labels);
+
+        let error = RestError::from_status(status, code, message);
+        if error.is_server_error() {
+            let labels = [("status", status.to_string())];
+            metrics::increment_counter!("ece_api_server_errors_total", &labels);
+            log::error!(
+                "{{\"path\": \"{}\", \"status\": {}, \"error\": {}}}",
+                path,
+                status,
+                error
+            );
+        }
+        Err(error)
+    }
 
+    fn build_request(
+        &self,
+        method: &Method,
+        uri: &str,
+        payload: &Option<Value>,
+        conditional: Option<HeaderValue>,
+    ) -> Request<RequestBody> {
+        let body = match payload {
+            Some(payload) => RequestBody::from(payload.to_string()),
+            None => RequestBody::default(),
+        };
         let mut req = Request::builder()
-            .method("GET")
-            .uri(&uri)
-            .body(Body::empty())
+            .method(method)
+            .uri(uri)
+            .body(body)
             .expect("request builder");
 
         let headers = req.headers_mut();
+        headers.insert(USER_AGENT, self.user_agent.clone());
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+        for (name, value) in self.headers.iter() {
+            headers.append(name.clone(), value.clone());
+        }
+        if let Some(etag) = conditional {
+            headers.insert(IF_NONE_MATCH, etag);
+        }
+        if payload.is_some() {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
 
-        if let Some(api_key) = &self.api_key {
-            let value = format!("ApiKey {}", api_key);
-            log::debug!("Adding authorization header: {}", &value);
-            let header =
-                HeaderValue::from_str(&value).expect("failed to convert credential header");
+        if let Some(header) = self.credentials.read().unwrap().request_authorization() {
             headers.insert(AUTHORIZATION, header);
-        } else {
-            let credentials = Credentials::new(
-                &self.username.as_ref().unwrap(),
-                &self.password.as_ref().unwrap(),
-            );
-            let credentials = credentials.as_http_header();
-            headers.insert(
-                AUTHORIZATION,
-                HeaderValue::from_str(&credentials).expect("failed to convert credential header"),
-            );
-        };
+        }
+        req
+    }
 
-        // Send initial request
-        let response = match self.client.request(req).await {
-            Ok(s) => s,
-            Err(e) => {
+    // Send a request, returning the response along with the deadline its body
+    // must also be read by
+    async fn send(
+        &self,
+        req: Request<RequestBody>,
+        path: &str,
+    ) -> Result<(Response<ResponseBody>, tokio::time::Instant), RestError> {
+        let start = Instant::now();
+        let deadline = tokio::time::Instant::now() + self.client.request_timeout();
+        let result = tokio::time::timeout_at(deadline, self.client.send(req)).await;
+        let labels = [("path", path_label(path))];
+        let elapsed = start.elapsed().as_secs_f64();
+        metrics::histogram!("ece_api_request_duration_seconds", elapsed, &labels);
+        native::observe("ece_api_request_duration_seconds", &labels, elapsed);
+
+        // ECE counts as available whenever it answers without a server error
+        let available = matches!(&result, Ok(Ok(response)) if !response.status().is_server_error());
+        {
+            let mut availability = self.availability.lock().unwrap();
+            availability.record(available);
+            // The window is a label, as the recorder strips digits from metric names
+            for (window, minutes) in [("5m", 5), ("1h", 60)] {
+                if let Some(ratio) = availability.ratio(minutes) {
+                    metrics::gauge!("ece_api_availability_ratio", ratio, "window" => window);
+                }
+            }
+        }
+
+        match result {
+            Ok(Ok(response)) => Ok((response, deadline)),
+            Err(_) => Err(self.timed_out(path)),
+            Ok(Err(e)) => {
                 log::error!("{{\"error\":\"{}\"", e);
-                return Err(RestError::Hyper(e));
+                Err(e)
             }
+        }
+    }
+
+    // ECE took longer than the request timeout to send a whole response
+    fn timed_out(&self, path: &str) -> RestError {
+        log::error!(
+            "{{\"path\": \"{}\", \"error\": \"request timed out\"}}",
+            path
+        );
+        RestError::GatewayTimeout(format!(
+            "no response from ECE within {}s",
+            self.client.request_timeout().as_secs()
+        ))
+    }
+
+    // Log in for a session token when there is none, it is about to expire, or
+    // a renewal is forced after ECE rejected it
+    async fn ensure_session(
+        &self,
+        force: bool,
+        path: &str,
+        authorization: &Option<HeaderValue>,
+    ) -> Result<(), RestError> {
+        let _guard = self.login_lock.lock().await;
+        let (username, password) = {
+            let credentials = self.credentials.read().unwrap();
+            let current = credentials.session.as_ref().filter(|s| !s.expiring());
+            // Another request may have logged in while this one waited for the lock
+            if let (Some(_), false) = (current, force) {
+                return Ok(());
+            }
+            (
+                credentials.username.clone().unwrap_or_default(),
+                credentials.password.clone().unwrap_or_default(),
+            )
         };
 
-        match response.status().as_u16() {
-            404 => return Err(RestError::NotFound),
-            403 => return Err(RestError::Forbidden),
-            401 => return Err(RestError::Unauthorized),
-            200 => Ok(response),
-            _ => {
-                log::error!(
-                    "Got bad status code from ECE: {}",
-                    response.status().as_u16()
-                );
-                let bytes = hyper::body::to_bytes(response.into_body()).await?;
-                let value: Value = serde_json::from_slice(&bytes)?;
-                log::error!("Bad response body: {}", value);
-                return Err(RestError::UnknownCode);
+        match session::login(
+            self.client.as_ref(),
+            &self.url,
+            &self.user_agent,
+            &username,
+            &password,
+        )
+        .await
+        {
+            Ok(session) => {
+                self.credentials.write().unwrap().session = Some(session);
+                Ok(())
+            }
+            Err(e) => {
+                if let RestError::Api {
+                    status: 401 | 403, ..
+                } = e
+                {
+                    self.record_auth_failure(path, authorization.clone());
+                }
+                self.credentials.write().unwrap().session = None;
+                Err(e)
             }
         }
     }
 
-    pub async fn get_allocators(&self) -> Result<allocator::AllocatorsRoot, RestError> {
-        let body = self
-            .get("api/v1/platform/infrastructure/allocators")
-            .await?;
-        let bytes = hyper::body::to_bytes(body.into_body()).await?;
-        let value: allocator::AllocatorsRoot = serde_json::from_slice(&bytes)?;
-        Ok(value)
+    fn record_auth_failure(&self, path: &str, authorization: Option<HeaderValue>) {
+        log::warn!(
+            "\"ECE rejected the credentials on {}, pausing requests for {}s\"",
+            path,
+            self.auth_cooldown.as_secs()
+        );
+        metrics::gauge!("ece_auth_failed", 1f64);
+        *self.auth_failure.lock().unwrap() = Some(AuthFailure {
+            until: Instant::now() + self.auth_cooldown,
+            authorization,
+        });
     }
 
-    pub async fn get_proxies(&self) -> Result<proxy::ProxiesRoot, RestError> {
-        let body = self.get("api/v1/platform/infrastructure/proxies").await?;
-        let bytes = hyper::body::to_bytes(body.into_body()).await?;
-        let value: proxy::ProxiesRoot = serde_json::from_slice(&bytes)?;
-        Ok(value)
+    // Deserialize a response while it streams in, rather than buffering the whole body first
+    pub async fn get_json<T>(&self, path: &str) -> Result<T, RestError>
+    where
+        T: DeserializeOwned + Serialize + Clone + Send + Sync + 'static,
+    {
+        self.request_json(Method::GET, path, None).await
     }
 
-    pub async fn parse_proxies(&self) -> Result<(), RestError> {
-        let body = self.get_proxies().await?;
-        log::debug!("{:#?}", body);
+    #[cfg(feature = "deployments")]
+    pub async fn post_json<T>(&self, path: &str, payload: Value) -> Result<T, RestError>
+    where
+        T: DeserializeOwned + Serialize + Clone + Send + Sync + 'static,
+    {
+        self.request_json(Method::POST, path, Some(payload)).await
+    }
 
-        for proxy in body.proxies {
-            log::debug!("\"Working on proxy: {}\"", proxy.proxy_id);
-            let labels = [
-                ("zone", proxy.zone.clone()),
-                ("hostname", proxy.public_hostname.to_owned()),
-                ("proxy_id", proxy.proxy_id.to_owned()),
-                (
-                    "proxy_ip",
-                    proxy.proxy_ip.unwrap_or("null".to_string()).to_owned(),
-                ),
-                ("healthy", proxy.healthy.to_string()),
-            ];
-            metrics::gauge!("ece_proxy_info", 1f64, &labels);
+    // Fetch a response, falling back to the persisted snapshot after a restart
+    pub async fn request_json<T>(
+        &self,
+        method: Method,
+        path: &str,
+        payload: Option<Value>,
+    ) -> Result<T, RestError>
+    where
+        T: DeserializeOwned + Serialize + Clone + Send + Sync + 'static,
+    {
+        let result = match self.fetch_json(method, path, payload).await {
+            Ok(value) => {
+                self.fetched_at
+                    .lock()
+                    .unwrap()
+                    .insert(path.to_string(), SystemTime::now());
+                self.keep_good(path, &value);
+                Ok(value)
+            }
+            Err(e) => {
+                if self.etag_cache.lock().unwrap().contains_key(path) {
+                    cache_event("ece_cache_refresh_failures_total", "etag");
+                }
+                match self.stale::<T>(path) {
+                    Some(value) => {
+                        log::warn!(
+                            "{{\"path\": \"{}\", \"error\": {}, \"message\": \"serving stale response\"}}",
+                            path,
+                            e
+                        );
+                        // Kept from before the failure, so not persisted as if fetched now
+                        if self.snapshot.is_some() {
+                            let labels = [("path", path_label(path))];
+                            metrics::gauge!("ece_snapshot_fresh", 0f64, &labels);
+                        }
+                        return Ok(value);
+                    }
+                    None => Err(e),
+                }
+            }
+        };
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot.clone(),
+            None => return result,
+        };
+
+        let labels = [("path", path_label(path))];
+        match result {
+            Ok(value) => {
+                metrics::gauge!("ece_snapshot_fresh", 1f64, &labels);
+                snapshot.store(path, serde_json::to_value(&value)?);
+                Ok(value)
+            }
+            Err(e) => match snapshot.restored::<T>(path) {
+                Some((value, fetched)) => {
+                    self.fetched_at
+                        .lock()
+                        .unwrap()
+                        .insert(path.to_string(), fetched);
+                    log::warn!(
+                        "{{\"path\": \"{}\", \"error\": {}, \"message\": \"serving persisted snapshot\"}}",
+                        path,
+                        e
+                    );
+                    metrics::gauge!("ece_snapshot_fresh", 0f64, &labels);
+                    Ok(value)
+                }
+                None => Err(e),
+            },
         }
-        Ok(())
     }
 
-    pub async fn parse_allocators(&self) -> Result<(), RestError> {
-        let body = self.get_allocators().await?;
-        log::debug!("{:#?}", body);
+    // Write the responses stored during a collection out in one go
+    pub async fn flush_snapshot(&self) {
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot.clone(),
+            None => return,
+        };
+        let writer = snapshot.clone();
+        let flushed = tokio::task::spawn_blocking(move || writer.flush())
+            .await
+            .expect("snapshot write panicked");
+        if let Err(e) = flushed {
+            log::error!(
+                "{{\"file\": \"{}\", \"error\": \"unable to write snapshot: {}\"}}",
+                snapshot.path().display(),
+                e
+            );
+        }
+    }
 
-        // Calculate seconds since month start
-        let now = chrono::Utc::now();
-        let month_start = NaiveDate::from_ymd_opt(now.year(), now.month(), 1u32)
-            .unwrap()
-            .and_hms_opt(0, 0, 0)
-            .unwrap();
-        let month_start_utc = DateTime::<Utc>::from_utc(month_start, Utc);
-        let seconds_since_month_start =
-            now.signed_duration_since(month_start_utc).num_seconds() as f64;
-
-        log::debug!("\"Seconds in month: {}\"", seconds_since_month_start);
-
-        // Cents per GB for current month
-        let cents_per_gb_current_month: f64 =
-            (self.eru_cost as f64 * 100.0 / 31536000.0) * seconds_since_month_start;
-        log::debug!(
-            "\"cents per gb for current month: {}\"",
-            cents_per_gb_current_month
+    // Remember a response for --stale-while-revalidate
+    fn keep_good<T>(&self, path: &str, value: &T)
+    where
+        T: Clone + Send + Sync + 'static,
+    {
+        if self.stale_while_revalidate.is_zero() {
+            return;
+        }
+        let value = Arc::new(value.clone());
+        self.last_good.lock().unwrap().insert(
+            path.to_string(),
+            LastGood {
+                fetched: Instant::now(),
+                value,
+            },
         );
+    }
 
-        for zone in body.zones {
-            log::debug!("\"Working in zone: {}\"", zone.zone_id);
-            for allocator in zone.allocators {
-                log::debug!("\"Working in allocator: {}\"", allocator.public_hostname);
+    // The last good response for a path, if still within the stale window
+    fn stale<T>(&self, path: &str) -> Option<T>
+    where
+        T: Clone + 'static,
+    {
+        if self.stale_while_revalidate.is_zero() {
+            return None;
+        }
+        cache_event("ece_cache_refresh_failures_total", "stale");
+        let last_good = self.last_good.lock().unwrap();
+        let value = last_good
+            .get(path)
+            .filter(|last| last.fetched.elapsed() < self.stale_while_revalidate)
+            .and_then(|last| last.value.downcast_ref::<T>().cloned());
+        let value = match value {
+            Some(value) => value,
+            None => {
+                cache_event("ece_cache_misses_total", "stale");
+                return None;
+            }
+        };
+        cache_event("ece_cache_hits_total", "stale");
+        self.served_stale.store(true, Ordering::Relaxed);
+        Some(value)
+    }
 
-                // Generate a set of standard labels for allocator
-                let mut alloc_tags = Vec::new();
-                for tag in &allocator.metadata {
-                    let key = tag.key.to_owned();
-                    alloc_tags.push((key, tag.value.clone()))
-                }
+    async fn fetch_json<T>(
+        &self,
+        method: Method,
+        path: &str,
+        payload: Option<Value>,
+    ) -> Result<T, RestError>
+    where
+        T: DeserializeOwned + Clone + Send + Sync + 'static,
+    {
+        // Hold a slot until the body is read, as that still occupies the connection
+        let _permit = self
+            .request_slots
+            .acquire()
+            .await
+            .expect("request semaphore closed");
 
-                let mut labels = vec![
-                    ("zone".to_string(), zone.zone_id.clone()),
-                    ("ip".to_string(), allocator.public_hostname.to_owned()),
-                    (
-                        "connected".to_string(),
-                        allocator.status.connected.to_string(),
-                    ),
-                    ("healthy".to_string(), allocator.status.healthy.to_string()),
-                    (
-                        "maintenance".to_string(),
-                        allocator.status.maintenance_mode.to_string(),
-                    ),
-                ];
-
-                // Include allocator tags
-                for tag in &alloc_tags {
-                    labels.push(tag.clone())
-                }
+        // Only ask for a 304 when the cached response can be reused as T, as a
+        // custom collector may fetch a built-in collector's path as plain JSON
+        let cacheable = method == Method::GET;
+        let conditional = match cacheable {
+            true => self
+                .etag_cache
+                .lock()
+                .unwrap()
+                .get(path)
+                .filter(|cached| cached.value.is::<T>())
+                .map(|cached| cached.etag.clone()),
+            false => None,
+        };
+        let (body, deadline) = self.request(method, path, payload, conditional).await?;
 
-                metrics::gauge!("ece_allocator_info", 1f64, &labels);
+        // Reuse the previous snapshot when ECE reports it unchanged
+        if body.status() == StatusCode::NOT_MODIFIED {
+            let cached = self.etag_cache.lock().unwrap().get(path).cloned();
+            if let Some(value) = cached.and_then(|c| c.value.downcast_ref::<T>().cloned()) {
+                let labels = [("path", path_label(path))];
+                metrics::increment_counter!("ece_api_not_modified_total", &labels);
+                cache_event("ece_cache_hits_total", "etag");
+                return Ok(value);
+            }
+            return Err(RestError::BadGateway(format!(
+                "ECE returned 304 for {} without a cached response",
+                path
+            )));
+        }
+        if cacheable {
+            cache_event("ece_cache_misses_total", "etag");
+        }
+        let etag = body.headers().get(ETAG).cloned();
 
-                let mut labels = vec![
-                    ("zone".to_string(), zone.zone_id.clone()),
-                    ("ip".to_string(), allocator.public_hostname.to_owned()),
-                ];
+        // Reject oversized responses up front when the length is known
+        let content_length = body
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if let Some(length) = content_length {
+            if length > self.max_response_bytes {
+                return Err(RestError::ResponseTooLarge(self.max_response_bytes));
+            }
+        }
+
+        let gzip = is_gzip(&body);
 
-                // Include allocator tags
-                for tag in &alloc_tags {
-                    labels.push(tag.clone())
+        let max_bytes = self.max_response_bytes;
+        let reader = BodyReader::new(body.into_body(), max_bytes, deadline);
+        let received = reader.received();
+        let timed_out = reader.timed_out();
+        let decoded = Arc::new(AtomicU64::new(0));
+        let decoded_counter = decoded.clone();
+        let tee = self.debug_token.is_some() && DEBUG_PATHS.contains(&path);
+        let (value, raw) = tokio::task::spawn_blocking(move || {
+            // Decompress while parsing, bounding the inflated size as well
+            let reader: Box<dyn Read> = match gzip {
+                true => Box::new(LimitedReader::new(
+                    GzDecoder::new(reader),
+                    max_bytes,
+                    decoded_counter,
+                )),
+                false => Box::new(reader),
+            };
+            // Keep the raw payload for the debug endpoints while it is parsed
+            match tee {
+                true => {
+                    let mut reader = BufReader::new(TeeReader::new(reader));
+                    let value = serde_json::from_reader::<_, T>(&mut reader);
+                    (value, Some(reader.into_inner().into_copy()))
                 }
+                false => (
+                    serde_json::from_reader::<_, T>(BufReader::new(reader)),
+                    None,
+                ),
+            }
+        })
+        .await
+        .expect("response parsing panicked");
 
-                metrics::gauge!(
-                    "ece_allocator_memory_used",
-                    allocator.capacity.memory.used.clone() as f64,
-                    &labels
-                );
-                metrics::gauge!(
-                    "ece_allocator_memory_total",
-                    allocator.capacity.memory.total.clone() as f64,
-                    &labels
-                );
-                metrics::gauge!(
-                    "ece_allocator_instances_total",
-                    allocator.instances.len() as f64,
-                    &labels
-                );
+        let received = received.load(Ordering::Relaxed);
+        let labels = [("path", path_label(path))];
+        metrics::gauge!("ece_api_response_bytes", received as f64, &labels);
+        if received > max_bytes || decoded.load(Ordering::Relaxed) > max_bytes {
+            return Err(RestError::ResponseTooLarge(self.max_response_bytes));
+        }
+        if timed_out.load(Ordering::Relaxed) {
+            return Err(self.timed_out(path));
+        }
+        let value = value?;
+        let size = match gzip {
+            true => decoded.load(Ordering::Relaxed),
+            false => received,
+        };
+        self.response_bytes
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), size);
+        if let Some(raw) = raw {
+            self.raw_responses
+                .lock()
+                .unwrap()
+                .insert(path.to_string(), Bytes::from(raw));
+        }
 
-                for instance in allocator.instances {
-                    let cluster_name = instance
-                        .cluster_name
-                        .unwrap_or("null".to_string())
-                        .to_owned();
-                    let cluster_healthy = match instance.cluster_healthy {
-                        Some(t) => t.to_string(),
-                        None => "null".to_string(),
-                    };
-                    log::debug!("\"Working in instance: {}\"", &cluster_name);
-                    let mut labels = vec![
-                        ("zone".to_string(), zone.zone_id.clone()),
-                        ("ip".to_string(), allocator.public_hostname.to_owned()),
-                        ("name".to_string(), cluster_name.clone()),
-                        (
-                            "cluster_type".to_string(),
-                            instance.cluster_type.to_string(),
-                        ),
-                        ("cluster_id".to_string(), instance.cluster_id.to_owned()),
-                        (
-                            "configuration_id".to_string(),
-                            instance.instance_configuration_id.to_owned(),
-                        ),
-                        (
-                            "deployment_id".to_string(),
-                            instance
-                                .deployment_id
-                                .unwrap_or("null".to_string())
-                                .to_owned(),
-                        ),
-                        (
-                            "healthy".to_string(),
-                            instance.healthy.unwrap_or(false).to_string(),
-                        ),
-                        ("cluster_healthy".to_string(), cluster_healthy.to_owned()),
-                        (
-                            "moving".to_string(),
-                            instance.moving.unwrap_or(false).to_string(),
-                        ),
-                    ];
-
-                    // Include allocator tags
-                    for tag in &alloc_tags {
-                        labels.push(tag.clone())
-                    }
-                    metrics::gauge!("ece_allocator_instance_info", 1f64, &labels);
-
-                    let mut labels = vec![
-                        ("zone".to_string(), zone.zone_id.clone()),
-                        ("ip".to_string(), allocator.public_hostname.to_owned()),
-                        ("name".to_string(), cluster_name.clone()),
-                        (
-                            "cluster_type".to_string(),
-                            instance.cluster_type.to_string(),
-                        ),
-                        ("cluster_id".to_string(), instance.cluster_id.to_owned()),
-                    ];
-                    // Include allocator tags
-                    for tag in &alloc_tags {
-                        labels.push(tag.clone())
-                    }
-                    metrics::gauge!(
-                        "ece_allocator_instance_node_memory",
-                        instance.node_memory.clone() as f64,
-                        &labels
+        let mut etag_cache = self.etag_cache.lock().unwrap();
+        match etag {
+            Some(etag) if cacheable => {
+                let value = Arc::new(value.clone());
+                etag_cache.insert(path.to_string(), CachedResponse { etag, value });
+            }
+            _ => {
+                etag_cache.remove(path);
+            }
+        }
+        Ok(value)
+    }
+
+    // ECE version, looked up once; the allocator models accept both the 2.x and
+    // 3.x payloads, so this only labels the platform and flags unknown versions
+    pub async fn platform_version(&self) -> Option<String> {
+        if let Some(version) = self.platform_version.lock().unwrap().clone() {
+            return Some(version);
+        }
+        match self
+            .get_json::<allocator::PlatformInfo>(PLATFORM_PATH)
+            .await
+        {
+            Ok(platform) => {
+                let major = platform.version.split('.').next().unwrap_or_default();
+                if major != "2" && major != "3" {
+                    log::warn!(
+                        "{{\"msg\": \"untested ECE version, payloads may not parse\", \"version\": \"{}\"}}",
+                        platform.version
                     );
+                }
+                *self.platform_version.lock().unwrap() = Some(platform.version.clone());
+                Some(platform.version)
+            }
+            Err(e) => {
+                log::warn!(
+                    "{{\"msg\": \"unable to detect ECE version\", \"error\": {}}}",
+                    e
+                );
+                None
+            }
+        }
+    }
 
-                    // Size of cluster in GB: {{ Cluster size in MB }} / 1024
-                    let cluster_size_gb: f64 = instance.node_memory as f64 / 1024.0;
+    pub async fn get_deployments_list(&self) -> Result<deployment::DeploymentsListRoot, RestError> {
+        self.get_json(DEPLOYMENTS_PATH).await
+    }
 
-                    let cluster_cost_over_month =
-                        (cluster_size_gb / 64.0) * cents_per_gb_current_month;
+    // Names for the ids in ECE payloads, served from cache until the ttl expires.
+    // Lookup failures fall back to the previous names rather than failing the caller.
+    pub async fn names(&self) -> Arc<Names> {
+        let (names, fresh) = {
+            let cache = self.name_cache.lock().unwrap();
+            let fresh = match cache.fetched {
+                Some(fetched) => fetched.elapsed() < self.name_cache_ttl,
+                None => false,
+            };
+            (cache.names.clone(), fresh)
+        };
+        if fresh {
+            cache_event("ece_cache_hits_total", "names");
+            return names;
+        }
+        cache_event("ece_cache_misses_total", "names");
 
-                    // Get instance cost per month
-                    metrics::gauge!(
-                        "ece_allocator_instance_monthly_cost",
-                        cluster_cost_over_month as f64,
-                        &labels
+        let mut refreshed = Names::clone(&names);
+        let mut failed = false;
+        // Deployment names only enrich per-instance labels, so skip the lookup without them
+        if !self.aggregate_only {
+            match self.get_deployments_list().await {
+                Ok(body) => refreshed.set_deployments(&body),
+                Err(e) => {
+                    failed = true;
+                    log::warn!(
+                        "{{\"msg\": \"unable to refresh deployment names\", \"error\": {}}}",
+                        e
                     );
+                }
+            }
+        }
+        match self
+            .get_json::<Vec<InstanceConfiguration>>(INSTANCE_CONFIGURATIONS_PATH)
+            .await
+        {
+            Ok(configurations) => refreshed.set_configurations(&configurations),
+            Err(e) => {
+                failed = true;
+                log::warn!(
+                    "{{\"msg\": \"unable to refresh instance configuration names\", \"error\": {}}}",
+                    e
+                );
+            }
+        }
 
-                    if let Some(plans_info) = instance.plans_info {
-                        let mut labels = vec![
-                            ("zone".to_string(), zone.zone_id.clone()),
-                            (
-                                "allocator".to_string(),
-                                allocator.public_hostname.to_owned(),
-                            ),
-                            ("name".to_string(), cluster_name.clone()),
-                            ("pending".to_string(), plans_info.pending.to_string()),
-                            (
-                                "version".to_string(),
-                                plans_info.version.unwrap_or("0".to_string()).to_owned(),
-                            ),
-                            (
-                                "cluster_type".to_string(),
-                                instance.cluster_type.to_string(),
-                            ),
-                            (
-                                "zone_count".to_string(),
-                                plans_info.zone_count.unwrap_or(0u64).to_string(),
-                            ),
-                        ];
-                        // Include allocator tags
-                        for tag in &alloc_tags {
-                            labels.push(tag.clone())
-                        }
-                        metrics::gauge!("ece_allocator_instance_plan", 1f64, &labels);
-                    }
+        let refreshed = Arc::new(refreshed);
+        let mut cache = self.name_cache.lock().unwrap();
+        cache.names = refreshed.clone();
+        if failed {
+            cache_event("ece_cache_refresh_failures_total", "names");
+        } else {
+            cache.fetched = Some(Instant::now());
+        }
+        refreshed
+    }
+
+    // Log, count, and notify the webhook of health transitions
+    pub fn record_transitions(&self, transitions: Vec<Event>) {
+        for event in transitions {
+            log::info!(
+                "{{\"msg\": \"health transition\", \"event\": {}}}",
+                serde_json::to_string(&event).unwrap_or_default()
+            );
+            #[cfg(feature = "push")]
+            if let Some(notifier) = &self.notifier {
+                notifier.transition(&event);
+            }
+            let mut labels = vec![("zone", event.zone)];
+            let counter = match (event.allocator, event.proxy_id, event.cluster_id) {
+                (_, Some(proxy_id), _) => {
+                    labels.push(("proxy_id", proxy_id));
+                    "ece_proxy_state_transitions_total"
+                }
+                // Per instance series are left out of aggregate-only output
+                (_, _, Some(_)) if self.aggregate_only => continue,
+                (allocator, _, Some(cluster_id)) => {
+                    labels.push(("ip", allocator.unwrap_or_default()));
+                    labels.push(("cluster_id", cluster_id));
+                    labels.push(("instance_name", event.instance_name.unwrap_or_default()));
+                    "ece_instance_state_transitions_total"
                 }
+                (allocator, _, None) => {
+                    labels.push(("ip", allocator.unwrap_or_default()));
+                    "ece_allocator_state_transitions_total"
+                }
+            };
+            labels.push(("state", event.state.to_string()));
+            labels.push(("from", event.from.to_string()));
+            labels.push(("to", event.to.to_string()));
+            metrics::increment_counter!(counter, &labels);
+        }
+    }
+
+    // Warn the webhook ahead of the license expiring
+    #[cfg(feature = "push")]
+    async fn check_license(&self, notifier: &Notifier) {
+        match self.get_json::<crate::license::License>(LICENSE_PATH).await {
+            Ok(license) => notifier.license(&license),
+            Err(e) => log::warn!(
+                "{{\"msg\": \"unable to check the ECE license\", \"error\": {}}}",
+                e
+            ),
+        }
+    }
+
+    pub fn collector_enabled(&self, name: &str) -> bool {
+        self.collectors.iter().any(|c| c == name)
+    }
+
+    // Run each enabled collector independently, returning the outcome of each
+    pub async fn get_metrics(&self) -> Vec<(&str, Result<(), RestError>)> {
+        let collection = Collection::default();
+        let mut results = Vec::new();
+        for collector in self.registry.iter() {
+            if self.collector_enabled(collector.name()) {
+                results.push((collector.name(), collector.collect(self, &collection).await));
+            }
+        }
+        results
+    }
+
+    // Collect in the background whenever a refresh is requested, for when no
+    // periodic collection loop is running
+    pub fn spawn_refresh_listener(&self) {
+        let state = self.clone();
+        tokio::spawn(async move {
+            loop {
+                state.refresh.notified().await;
+                log::info!("\"Refreshing ECE data on request\"");
+                state.collect().await;
             }
+        });
+    }
+
+    // Collect for a scrape. Within the --stale-while-revalidate window, a scrape
+    // arriving while a collection is in flight returns the last good data at once
+    // instead of waiting, and collections run in their own task so they finish
+    // even when the scraper gives up on them.
+    pub async fn scrape(&self) {
+        if self.stale_while_revalidate.is_zero() {
+            self.collect().await;
+            return;
+        }
+        let servable = self
+            .last_success
+            .lock()
+            .unwrap()
+            .map(|last| last.elapsed() < self.stale_while_revalidate)
+            .unwrap_or(false);
+        if servable && self.last_collect.try_lock().is_err() {
+            metrics::gauge!("ece_data_stale", 1f64);
+            return;
+        }
+        let state = self.clone();
+        if let Err(e) = tokio::spawn(async move { state.collect().await }).await {
+            log::error!("{{\"error\": \"collection failed: {}\"}}", e);
         }
-        Ok(())
     }
 
-    pub async fn get_metrics(&self) -> Result<(), RestError> {
-        self.parse_allocators().await?;
-        self.parse_proxies().await?;
-        Ok(())
+    // Run a collection, unless one finished while waiting for the lock, in which
+    // case concurrent scrapes share its outcome instead of calling ECE again
+    pub async fn collect(&self) -> bool {
+        let requested = Instant::now();
+        let mut last_collect = self.last_collect.lock().await;
+        if let Some((finished, success)) = *last_collect {
+            if finished >= requested {
+                metrics::increment_counter!("ece_scrapes_coalesced_total");
+                return success;
+            }
+        }
+        let success = self.run_collectors().await;
+        *last_collect = Some((Instant::now(), success));
+        success
+    }
+
+    // Run a collection, recording the outcome in ece_cluster_up. The
+    // collection only counts as failed when every collector failed.
+    async fn run_collectors(&self) -> bool {
+        metrics::increment_counter!("ece_scrapes_total");
+        // Heartbeat, bumped whatever the outcome so a dead exporter is told
+        // apart from a live one that can't reach ECE
+        metrics::increment_counter!("ece_exporter_collections_total");
+        self.served_stale.store(false, Ordering::Relaxed);
+        let results = self.get_metrics().await;
+        #[cfg(feature = "push")]
+        if let Some(notifier) = &self.notifier {
+            self.check_license(notifier).await;
+        }
+        for (collector, result) in &results {
+            let labels = [("collector", collector.to_string())];
+            // Age of the ECE response behind this collector's series, whether
+            // fetched just now, confirmed unchanged, or restored from disk
+            let fetched = self
+                .registry
+                .get(collector)
+                .and_then(|c| self.fetched_at.lock().unwrap().get(c.path()).copied());
+            if let Some(fetched) = fetched {
+                let age = fetched.elapsed().unwrap_or_default().as_secs_f64();
+                metrics::gauge!("ece_data_age_seconds", age, &labels);
+            }
+            let up = if result.is_ok() { 1f64 } else { 0f64 };
+            metrics::gauge!("ece_collector_up", up, &labels);
+            // Always touch the failure counter so it exists before the first failure
+            let failed = if result.is_ok() { 0 } else { 1 };
+            metrics::counter!("ece_scrape_failures_total", failed, &labels);
+            if let Err(e) = result {
+                log::error!("{{\"collector\": \"{}\", \"error\": {}}}", collector, e);
+                self.diagnostics
+                    .lock()
+                    .unwrap()
+                    .record_error(collector, e.to_string());
+            }
+        }
+        self.diagnostics.lock().unwrap().last_collect = Some(Utc::now().to_rfc3339());
+        self.flush_snapshot().await;
+
+        let success = results.iter().any(|(_, result)| result.is_ok());
+        metrics::gauge!("ece_cluster_up", if success { 1f64 } else { 0f64 });
+        let stale = self.served_stale.load(Ordering::Relaxed);
+        metrics::gauge!("ece_data_stale", if stale { 1f64 } else { 0f64 });
+        if success {
+            *self.last_success.lock().unwrap() = Some(Instant::now());
+        }
+        self.record_cache_bytes();
+        success
+    }
+
+    // Estimated memory held by each cache, to size the exporter's pod
+    fn record_cache_bytes(&self) {
+        let sizes = self.response_bytes.lock().unwrap().clone();
+        let held =
+            |paths: Vec<String>| -> u64 { paths.iter().filter_map(|path| sizes.get(path)).sum() };
+        let etag = held(self.etag_cache.lock().unwrap().keys().cloned().collect());
+        let stale = held(self.last_good.lock().unwrap().keys().cloned().collect());
+        let debug: usize = self
+            .raw_responses
+            .lock()
+            .unwrap()
+            .values()
+            .map(Bytes::len)
+            .sum();
+        let names = self.name_cache.lock().unwrap().names.bytes();
+        let mut caches = vec![
+            ("etag", etag as f64),
+            ("stale", stale as f64),
+            ("debug", debug as f64),
+            ("names", names as f64),
+        ];
+        if let Some(snapshot) = &self.snapshot {
+            caches.push(("snapshot", snapshot.bytes() as f64));
+        }
+        for (cache, bytes) in caches {
+            metrics::gauge!("ece_exporter_cache_bytes", bytes, "cache" => cache);
+        }
+    }
+}
+
+// Template per-deployment paths so the path label stays bounded, e.g.
+// api/v1/deployments/{deployment_id}/elasticsearch/{ref_id}/remote-clusters
+fn path_label(path: &str) -> String {
+    let path = path.split('?').next().unwrap_or(path);
+    match path.strip_prefix("api/v1/deployments/") {
+        Some(rest) if rest.contains('/') => {
+            let mut segments: Vec<&str> = rest.split('/').collect();
+            segments[0] = "{deployment_id}";
+            if segments.len() > 2 {
+                segments[2] = "{ref_id}";
+            }
+            format!("api/v1/deployments/{}", segments.join("/"))
+        }
+        _ => path.to_string(),
+    }
+}
+
+// Count a lookup in, or a failed refresh of, one of the exporter's caches
+fn cache_event(counter: &'static str, cache: &'static str) {
+    metrics::increment_counter!(counter, "cache" => cache);
+}
+
+// Whether ECE compressed the response body
+fn is_gzip(response: &Response<ResponseBody>) -> bool {
+    response
+        .headers()
+        .get(CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.eq_ignore_ascii_case("gzip"))
+        .unwrap_or(false)
+}
+
+// Retry-After may be either a number of seconds or an HTTP date
+fn parse_retry_after(value: &str) -> Option<u64> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(secs);
+    }
+    let date = DateTime::parse_from_rfc2822(value.trim()).ok()?;
+    let secs = date.signed_duration_since(Utc::now()).num_seconds();
+    Some(secs.max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{collect, fixture, gauge, state, MockApi};
+    use crate::proxy;
+
+    #[tokio::test]
+    async fn parse_proxies_serves_stale_response_on_failure() {
+        let api = Arc::new(MockApi::new().respond(
+            PROXIES_PATH,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"errors": [{"code": "root.unavailable", "message": "down"}]}"#,
+        ));
+        let state = state(api, &["--stale-while-revalidate", "60"]).await;
+        let error = collect(&state, "proxies").await.unwrap_err();
+        assert!(error.is_server_error());
+
+        let proxies: proxy::ProxiesRoot = serde_json::from_str(fixture!("proxies")).unwrap();
+        state.keep_good(PROXIES_PATH, &proxies);
+        collect(&state, "proxies").await.unwrap();
+        assert!(state.served_stale.load(Ordering::Relaxed));
+        assert_eq!(state.diagnostics.lock().unwrap().proxies, 2);
+    }
+
+    #[tokio::test]
+    async fn oversized_error_bodies_are_not_read() {
+        let api = Arc::new(MockApi::new().respond(
+            PROXIES_PATH,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"errors": [{"code": "root.unavailable", "message": "down"}]}"#,
+        ));
+        let state = state(api, &["--max-response-bytes", "16"]).await;
+        let error = collect(&state, "proxies").await.unwrap_err();
+        assert!(error.is_server_error());
+        assert!(!error.to_string().contains("down"));
+    }
+
+    #[tokio::test]
+    async fn stale_responses_keep_their_snapshot_age() {
+        let api = Arc::new(MockApi::new().respond(
+            PROXIES_PATH,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"errors": [{"code": "root.unavailable", "message": "down"}]}"#,
+        ));
+        let file = std::env::temp_dir().join(format!("ece-stale-{}.json", std::process::id()));
+        let proxies: Value = serde_json::from_str(fixture!("proxies")).unwrap();
+        let persisted = serde_json::json!({
+            PROXIES_PATH: {"fetched": 1000, "value": proxies}
+        });
+        std::fs::write(&file, persisted.to_string()).unwrap();
+        let args = [
+            "--stale-while-revalidate",
+            "60",
+            "--snapshot-file",
+            file.to_str().unwrap(),
+        ];
+        let state = state(api, &args).await;
+
+        let proxies: proxy::ProxiesRoot = serde_json::from_value(proxies).unwrap();
+        state.keep_good(PROXIES_PATH, &proxies);
+        collect(&state, "proxies").await.unwrap();
+        assert!(state.served_stale.load(Ordering::Relaxed));
+        let labels = [("path", PROXIES_PATH)];
+        assert_eq!(gauge("ece_snapshot_fresh", &labels), Some(0.0));
+
+        state.flush_snapshot().await;
+        let (_, fetched) = Snapshot::load(file.to_str().unwrap())
+            .restored::<Value>(PROXIES_PATH)
+            .unwrap();
+        assert_eq!(fetched, SystemTime::UNIX_EPOCH + Duration::from_secs(1000));
+        std::fs::remove_file(&file).unwrap();
     }
 }
@@ -0,0 +1,211 @@
+use crate::allocator::{Allocator, Instance};
+
+// Labels every instance series already carries, which a template can't replace
+const RESERVED_LABELS: &[&str] = &[
+    "allocator",
+    "cluster_healthy",
+    "cluster_id",
+    "cluster_type",
+    "configuration_id",
+    "configuration_name",
+    "deployment_id",
+    "deployment_name",
+    "healthy",
+    "instance_name",
+    "ip",
+    "moving",
+    "name",
+    "pending",
+    "version",
+    "zone",
+    "zone_count",
+];
+
+// A custom instance label rendered from a template such as {{tag:team}}-{{zone}}
+#[derive(Debug, Clone)]
+pub struct LabelTemplate {
+    pub name: String,
+    pub template: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(Field),
+    Tag(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Field {
+    Zone,
+    AllocatorId,
+    Hostname,
+    HostIp,
+    ClusterId,
+    ClusterName,
+    ClusterType,
+    InstanceName,
+    ConfigurationId,
+    DeploymentId,
+    DeploymentName,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "zone" => Field::Zone,
+            "allocator_id" => Field::AllocatorId,
+            "hostname" => Field::Hostname,
+            "host_ip" => Field::HostIp,
+            "cluster_id" => Field::ClusterId,
+            "cluster_name" => Field::ClusterName,
+            "cluster_type" => Field::ClusterType,
+            "instance_name" => Field::InstanceName,
+            "configuration_id" => Field::ConfigurationId,
+            "deployment_id" => Field::DeploymentId,
+            "deployment_name" => Field::DeploymentName,
+            _ => return None,
+        })
+    }
+}
+
+// The parsed data a template is evaluated against
+pub struct Context<'a> {
+    pub zone: &'a str,
+    pub allocator: &'a Allocator,
+    pub instance: &'a Instance,
+    pub cluster_name: &'a str,
+    pub deployment_name: &'a str,
+}
+
+impl LabelTemplate {
+    // Parse a "label=template" option value
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, template) = match spec.split_once('=') {
+            Some((name, template)) => (name.trim(), template.trim()),
+            None => {
+                return Err(format!(
+                    "instance-label {} must be in the form label=template",
+                    spec
+                ))
+            }
+        };
+        if !is_label_name(name) {
+            return Err(format!("instance-label {} is not a valid label name", name));
+        }
+        if RESERVED_LABELS.contains(&name) {
+            return Err(format!(
+                "instance-label {} would replace a built-in label",
+                name
+            ));
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find("}}")
+                .ok_or_else(|| format!("instance-label {} has an unclosed {{{{", name))?
+                + start;
+            let placeholder = rest[start + 2..end].trim();
+            let part = match placeholder.strip_prefix("tag:") {
+                Some(key) if !key.is_empty() => Part::Tag(key.to_string()),
+                Some(_) => return Err(format!("instance-label {} has an empty tag name", name)),
+                None => Part::Field(Field::parse(placeholder).ok_or_else(|| {
+                    format!(
+                        "instance-label {} uses unknown placeholder {{{{{}}}}}",
+                        name, placeholder
+                    )
+                })?),
+            };
+            parts.push(part);
+            rest = &rest[end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_string()));
+        }
+
+        Ok(LabelTemplate {
+            name: name.to_string(),
+            template: template.to_string(),
+            parts,
+        })
+    }
+
+    // Missing tags render as empty strings
+    pub fn render(&self, context: &Context) -> String {
+        let mut value = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => value.push_str(text),
+                Part::Tag(key) => {
+                    if let Some(tag) = context.allocator.metadata.iter().find(|t| &t.key == key) {
+                        value.push_str(&tag.value)
+                    }
+                }
+                Part::Field(field) => value.push_str(match field {
+                    Field::Zone => context.zone,
+                    Field::AllocatorId => &context.allocator.allocator_id,
+                    Field::Hostname => &context.allocator.public_hostname,
+                    Field::HostIp => &context.allocator.host_ip,
+                    Field::ClusterId => &context.instance.cluster_id,
+                    Field::ClusterName => context.cluster_name,
+                    Field::ClusterType => &context.instance.cluster_type,
+                    Field::InstanceName => &context.instance.instance_name,
+                    Field::ConfigurationId => &context.instance.instance_configuration_id,
+                    Field::DeploymentId => {
+                        context.instance.deployment_id.as_deref().unwrap_or("null")
+                    }
+                    Field::DeploymentName => context.deployment_name,
+                }),
+            }
+        }
+        value
+    }
+}
+
+// Prometheus label names match [a-zA-Z_][a-zA-Z0-9_]*
+pub fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::AllocatorsRoot;
+    use crate::mock::fixture;
+
+    #[test]
+    fn render_fills_fields_and_tags() {
+        let body: AllocatorsRoot = serde_json::from_str(fixture!("allocators")).unwrap();
+        let allocator = &body.zones[0].allocators[0];
+        let context = Context {
+            zone: &body.zones[0].zone_id,
+            allocator,
+            instance: &allocator.instances[0],
+            cluster_name: "logs",
+            deployment_name: "logging",
+        };
+
+        let template = LabelTemplate::parse("owner={{tag:team}}-{{ zone }}").unwrap();
+        assert_eq!(template.name, "owner");
+        assert_eq!(template.render(&context), "search-zone-1");
+        let template = LabelTemplate::parse("rack={{tag:missing}}/{{cluster_name}}").unwrap();
+        assert_eq!(template.render(&context), "/logs");
+
+        assert!(LabelTemplate::parse("owner={{nope}}").is_err());
+        assert!(LabelTemplate::parse("owner={{zone").is_err());
+        assert!(LabelTemplate::parse("zone={{tag:team}}").is_err());
+        assert!(LabelTemplate::parse("1owner=x").is_err());
+    }
+}
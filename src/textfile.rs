@@ -0,0 +1,62 @@
+use metrics_exporter_prometheus::PrometheusHandle;
+use rand::Rng;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+
+use crate::process;
+use crate::state::State;
+
+// Render the current metrics into path, replacing it atomically so that
+// node_exporter's textfile collector never reads a partial file
+pub fn write(path: &Path, handle: &PrometheusHandle) -> io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "missing file name"))?;
+    // node_exporter only reads *.prom files, so the temporary file is ignored
+    let tmp = path.with_file_name(format!(".{}.tmp", file_name.to_string_lossy()));
+    fs::write(&tmp, handle.render())?;
+    fs::rename(&tmp, path)
+}
+
+// Random delay of up to jitter, so replicas sharing an ECE don't all call it at once
+fn splay(jitter: Duration) -> Duration {
+    match jitter.is_zero() {
+        true => Duration::ZERO,
+        false => rand::thread_rng().gen_range(Duration::ZERO..=jitter),
+    }
+}
+
+// Collect and rewrite the textfile every interval, or early when a refresh is requested
+pub fn spawn(
+    state: State,
+    handle: PrometheusHandle,
+    path: String,
+    interval: Duration,
+    jitter: Duration,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // Slow collections and jitter push ticks back rather than bunching them up
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => tokio::time::sleep(splay(jitter)).await,
+                _ = state.refresh.notified() => log::info!("\"Refreshing ECE data on request\""),
+            }
+            state.collect().await;
+            process::record();
+            let written = tokio::task::block_in_place(|| write(Path::new(&path), &handle));
+            match written {
+                Ok(()) => log::debug!("\"Wrote metrics to {}\"", path),
+                Err(e) => log::error!(
+                    "{{\"path\": \"{}\", \"error\": \"unable to write textfile: {}\"}}",
+                    path,
+                    e
+                ),
+            }
+        }
+    });
+}
@@ -0,0 +1,76 @@
+use hyper::Request;
+use serde_json::Value;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use crate::body;
+use crate::credentials::Credentials;
+use crate::https::{HttpsClient, RequestBody};
+
+type BoxResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+// Location of the ECE api key within Vault KV
+#[derive(Clone, Debug)]
+pub struct VaultConfig {
+    pub addr: String,
+    pub path: String,
+    pub token: String,
+    pub key: String,
+    pub refresh_interval: Duration,
+}
+
+// Read the api key from Vault, supporting both KV v1 and v2 layouts
+pub async fn fetch_api_key(client: &HttpsClient, config: &VaultConfig) -> BoxResult<String> {
+    let uri = format!("{}/v1/{}", config.addr, config.path);
+    let req = Request::builder()
+        .method("GET")
+        .uri(&uri)
+        .header("X-Vault-Token", &config.token)
+        .body(RequestBody::default())
+        .expect("request builder");
+
+    let response = client.request(req).await?;
+    let status = response.status();
+    let bytes = body::to_bytes(response.into_body()).await?;
+    if !status.is_success() {
+        return Err(format!("vault returned {} for {}", status, config.path).into());
+    }
+
+    let value: Value = serde_json::from_slice(&bytes)?;
+    let data = &value["data"];
+    let secret = match data["data"].is_object() {
+        true => &data["data"][&config.key],
+        false => &data[&config.key],
+    };
+    match secret.as_str() {
+        Some(api_key) => Ok(api_key.to_string()),
+        None => Err(format!("key {} not found at vault path {}", config.key, config.path).into()),
+    }
+}
+
+// Periodically re-read the api key so rotations in Vault are picked up
+pub fn spawn_refresh(
+    client: HttpsClient,
+    config: VaultConfig,
+    credentials: Arc<RwLock<Credentials>>,
+) {
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(config.refresh_interval).await;
+            match fetch_api_key(&client, &config).await {
+                Ok(api_key) => {
+                    let mut credentials = credentials.write().unwrap();
+                    if credentials.api_key.as_ref() != Some(&api_key) {
+                        log::info!("\"Refreshed ECE api key from vault\"");
+                    }
+                    credentials.api_key = Some(api_key);
+                }
+                Err(e) => log::error!(
+                    "{{\"msg\": \"unable to refresh api key from vault\", \"error\": \"{}\"}}",
+                    e
+                ),
+            }
+        }
+    });
+}
@@ -0,0 +1,125 @@
+use chrono::Utc;
+use hyper::header::CONTENT_TYPE;
+use hyper::Request;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::body;
+use crate::events::Event;
+use crate::https::{HttpsClient, RequestBody};
+use crate::license::License;
+
+// Posts a JSON payload to --webhook-url when the infrastructure changes state,
+// for small setups without an alerting pipeline
+#[derive(Clone, Debug)]
+pub struct Notifier {
+    client: HttpsClient,
+    url: String,
+    license_warning: Duration,
+    // Expiry date already notified about, so a license is only reported once
+    license_notified: Arc<Mutex<Option<String>>>,
+}
+
+impl Notifier {
+    pub fn new(client: HttpsClient, url: String, license_warning: Duration) -> Self {
+        Notifier {
+            client,
+            url,
+            license_warning,
+            license_notified: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Notify about allocators disconnecting and proxies turning unhealthy
+    pub fn transition(&self, event: &Event) {
+        let kind = match event {
+            Event {
+                proxy_id: Some(_),
+                state: "healthy",
+                to: false,
+                ..
+            } => "proxy_unhealthy",
+            Event {
+                allocator: Some(_),
+                cluster_id: None,
+                state: "connected",
+                to: false,
+                ..
+            } => "allocator_disconnected",
+            _ => return,
+        };
+        let mut payload = serde_json::to_value(event).unwrap_or_else(|_| json!({}));
+        payload["event"] = json!(kind);
+        self.send(payload);
+    }
+
+    // Notify once when the license comes within the warning period of expiring
+    pub fn license(&self, license: &License) {
+        let remaining = license.expiry_date.signed_duration_since(Utc::now());
+        if remaining.num_seconds() > self.license_warning.as_secs() as i64 {
+            return;
+        }
+        let expiry = license.expiry_date.to_rfc3339();
+        {
+            let mut notified = self.license_notified.lock().unwrap();
+            if notified.as_deref() == Some(expiry.as_str()) {
+                return;
+            }
+            *notified = Some(expiry.clone());
+        }
+        self.send(json!({
+            "event": "license_expiring",
+            "time": Utc::now().to_rfc3339(),
+            "expiry_date": expiry,
+            "days_left": remaining.num_days(),
+            "type": license.license_type,
+        }));
+    }
+
+    // Deliver in the background so a slow webhook never holds up a collection
+    fn send(&self, payload: Value) {
+        let notifier = self.clone();
+        tokio::spawn(async move {
+            let event = payload["event"].as_str().unwrap_or_default().to_string();
+            let req = Request::builder()
+                .method("POST")
+                .uri(&notifier.url)
+                .header(CONTENT_TYPE, "application/json")
+                .body(RequestBody::from(payload.to_string()))
+                .expect("request builder");
+            let timeout = notifier.client.request_timeout();
+            let deadline = tokio::time::Instant::now() + timeout;
+            let result = match tokio::time::timeout_at(deadline, notifier.client.request(req)).await
+            {
+                Ok(Ok(response)) => {
+                    let status = response.status();
+                    // Drain the body so the connection can be reused
+                    let drained =
+                        tokio::time::timeout_at(deadline, body::to_bytes(response.into_body()));
+                    let _ = drained.await;
+                    match status.is_success() {
+                        true => Ok(()),
+                        false => Err(format!("webhook returned {}", status)),
+                    }
+                }
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err(format!("no response within {}s", timeout.as_secs())),
+            };
+            match result {
+                Ok(()) => log::info!(
+                    "{{\"msg\": \"webhook notified\", \"event\": \"{}\"}}",
+                    event
+                ),
+                Err(e) => {
+                    metrics::increment_counter!("ece_webhook_failures_total");
+                    log::error!(
+                        "{{\"msg\": \"webhook notification failed\", \"event\": \"{}\", \"error\": \"{}\"}}",
+                        event,
+                        e
+                    );
+                }
+            }
+        });
+    }
+}